@@ -1,4 +1,3 @@
 #!/bin/bash
 DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" >/dev/null 2>&1 && pwd )"
-cd $DIR
-./cloudflare-ddns
+"$DIR/cloudflare-ddns" --config "$DIR/config.yml" "$@"
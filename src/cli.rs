@@ -0,0 +1,109 @@
+use clap::{ArgAction, Parser, Subcommand};
+
+use std::{net::IpAddr, path::PathBuf};
+
+use cloudflare_ddns::config::Format;
+
+/// The exit codes of [`Error::exit_code`](cloudflare_ddns::error::Error::exit_code),
+/// and [`USAGE_ERROR`].
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  All records are up to date
+  1  Unexpected local error
+  2  The configuration could not be loaded
+  3  No public address could be detected
+  4  The DNS provider rejected the credentials
+  5  The DNS provider rejected a request
+  6  The DNS provider could not be reached
+  7  Some records were updated, others failed
+  64 The command line is invalid";
+
+/// Exit code for an invalid command line, apart from the codes of errors
+/// during a run. That of sysexits.h, as clap's own 2 is taken by `Config`.
+pub const USAGE_ERROR: u8 = 64;
+
+/// Keeps Cloudflare DNS records pointed at the public address of this host.
+#[derive(Debug, Parser)]
+#[command(version, about, after_help = EXIT_CODES)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Configuration file to use [default: the first of
+    /// ./config.{yml,yaml,toml,json} that exists]. Settings can also be given,
+    /// or overridden, with CF_DDNS_* environment variables.
+    #[arg(short, long, value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Format of the configuration file [default: by its extension, else YAML].
+    #[arg(long, value_name = "FORMAT", global = true)]
+    pub format: Option<Format>,
+
+    /// Keep running and check again every `interval` seconds.
+    #[arg(short, long)]
+    pub daemon: bool,
+
+    /// Look up the records and print the planned changes without applying them.
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Update records even if they already hold the current address.
+    #[arg(short, long)]
+    pub force: bool,
+
+    /// Look up the records again instead of trusting the state file.
+    #[arg(short, long)]
+    pub refresh: bool,
+
+    /// Use this address instead of detecting it. Can be given once per family.
+    #[arg(long, value_name = "ADDR")]
+    pub ip: Vec<IpAddr>,
+
+    /// Print more details.
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet", global = true)]
+    pub verbose: u8,
+
+    /// Only print errors.
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Check that the API token is active and can edit DNS records in every zone.
+    Verify,
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration after applying the environment and
+    /// command line, with credentials redacted.
+    Show {
+        /// Format to print.
+        #[arg(long, value_name = "FORMAT", default_value = "yaml")]
+        to: Format,
+    },
+    /// Check the configuration and report every problem found.
+    Check,
+    /// Print the configuration file translated to another format.
+    Convert {
+        /// Format to translate to.
+        #[arg(long, value_name = "FORMAT")]
+        to: Format,
+    },
+}
+
+impl Args {
+    pub fn verbosity(&self) -> i8 {
+        if self.quiet {
+            -1
+        } else {
+            self.verbose.min(i8::MAX as u8) as i8
+        }
+    }
+}
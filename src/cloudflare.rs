@@ -0,0 +1,348 @@
+//! A client for the parts of the Cloudflare API that manage DNS records.
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use std::time::Duration;
+
+use crate::{config::Auth, error::Error, secret::Secret, RecordType};
+
+/// Where the Cloudflare API is served unless configured otherwise.
+pub const API_BASE: &str = "https://api.cloudflare.com/client/v4";
+const REQUEST_TIMEOUT: u64 = 30;
+const PAGE_SIZE: usize = 100;
+/// The most zones Cloudflare lists per page.
+const ZONE_PAGE_SIZE: usize = 50;
+/// Cloudflare error codes that mean the credentials were rejected.
+const AUTH_ERROR_CODES: &[u32] = &[9103, 9106, 9109, 10000];
+
+/// A record to create, or the new contents of an existing one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateRecord {
+    #[serde(rename(serialize = "type", deserialize = "type"))]
+    pub ty: RecordType,
+    /// Relative to the zone, where `@` is the apex.
+    pub name: String,
+    pub content: String,
+    /// In seconds, where 1 is automatic.
+    pub ttl: usize,
+    pub proxied: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ApiMessage<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiError>,
+    result: Option<T>,
+    result_info: Option<ResultInfo>,
+}
+
+/// Pagination of list results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ResultInfo {
+    page: usize,
+    per_page: usize,
+    count: usize,
+    total_count: usize,
+    /// Not sent by every endpoint, see `ResultInfo::is_last_page`.
+    #[serde(default)]
+    total_pages: Option<usize>,
+}
+
+impl ResultInfo {
+    fn is_last_page(&self) -> bool {
+        let last = match self.total_pages {
+            Some(total_pages) => self.page >= total_pages,
+            None => self.page * self.per_page >= self.total_count,
+        };
+        last || self.count == 0
+    }
+}
+
+/// An error reported by the API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: u32,
+    pub message: String,
+}
+
+/// A DNS record as stored by Cloudflare.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiResult {
+    pub id: String,
+    #[serde(rename(serialize = "type", deserialize = "type"))]
+    pub ty: String,
+    /// Fully qualified name.
+    pub name: String,
+    pub content: String,
+    pub proxied: bool,
+    pub ttl: usize,
+    /// Deprecated by Cloudflare, so not always sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiZone {
+    pub id: String,
+    pub name: String,
+    /// What the current credentials may do in the zone, e.g. `#dns_records:edit`.
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+/// The API token the client authenticates with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    /// `active` if the token can be used.
+    pub status: String,
+    pub expires_on: Option<String>,
+}
+
+/// The user whose Global API Key the client authenticates with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiUser {
+    pub email: String,
+}
+
+/// Client for the Cloudflare API that sends the credentials with every request.
+///
+/// Rejected credentials are reported as `Error::Auth`, other failures as
+/// `Error::Api` or `Error::Network`.
+#[derive(Debug, Clone)]
+pub struct CloudflareClient {
+    http: reqwest::Client,
+    base: String,
+    /// Whether the credentials are an API token rather than a Global API Key.
+    token: bool,
+}
+
+impl CloudflareClient {
+    /// Fails if the credentials refer to a value that has not been resolved,
+    /// as done by `Config::load`.
+    pub fn new(auth: &Auth) -> Result<Self, Error> {
+        let mut headers = HeaderMap::new();
+        let mut insert = |name: HeaderName, value: &str| {
+            let mut value = HeaderValue::from_str(value)
+                .map_err(|_| Error::Config(format!("{} contains invalid characters", name)))?;
+            value.set_sensitive(true);
+            headers.insert(name, value);
+            Ok::<_, Error>(())
+        };
+        match auth {
+            Auth::Token { token } => insert(AUTHORIZATION, &format!("Bearer {}", token.expose()?))?,
+            Auth::Key { email, key } => {
+                insert(HeaderName::from_static("x-auth-email"), email)?;
+                insert(HeaderName::from_static("x-auth-key"), key.expose()?)?;
+            }
+        }
+
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .build()?;
+        Ok(CloudflareClient {
+            http,
+            base: API_BASE.to_string(),
+            token: matches!(auth, Auth::Token { .. }),
+        })
+    }
+
+    /// A client that authenticates with an API token.
+    pub fn with_token(token: &str) -> Result<Self, Error> {
+        Self::new(&Auth::Token {
+            token: Secret::from(token.to_string()),
+        })
+    }
+
+    /// A client that authenticates with the Global API Key of `email`.
+    pub fn with_key(email: &str, key: &str) -> Result<Self, Error> {
+        Self::new(&Auth::Key {
+            email: email.to_string(),
+            key: Secret::from(key.to_string()),
+        })
+    }
+
+    /// Sends the requests to `url` instead of the Cloudflare API, e.g. to test
+    /// against a mock server.
+    pub fn with_base_url(mut self, url: &str) -> Self {
+        self.base = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn uses_token(&self) -> bool {
+        self.token
+    }
+
+    /// Details of the API token, which only works when authenticating with one.
+    pub async fn verify_token(&self) -> Result<ApiToken, Error> {
+        let req = self.http.get(format!("{}/user/tokens/verify", self.base));
+        result(send(req).await?)
+    }
+
+    /// The user the credentials belong to.
+    pub async fn user(&self) -> Result<ApiUser, Error> {
+        let req = self.http.get(format!("{}/user", self.base));
+        result(send(req).await?)
+    }
+
+    /// The zones the credentials can access, only those called `name` if given.
+    pub async fn list_zones(&self, name: Option<&str>) -> Result<Vec<ApiZone>, Error> {
+        let query: Vec<_> = name
+            .map(|name| ("name", name.to_string()))
+            .into_iter()
+            .collect();
+        self.list_all(&format!("{}/zones", self.base), &query, ZONE_PAGE_SIZE)
+            .await
+    }
+
+    pub async fn zone(&self, zone_id: &str) -> Result<ApiZone, Error> {
+        let req = self.http.get(format!("{}/zones/{}", self.base, zone_id));
+        result(send(req).await?)
+    }
+
+    /// All records of the zone, only those of type `ty` if given, following
+    /// pagination.
+    pub async fn list_records(
+        &self,
+        zone_id: &str,
+        ty: Option<RecordType>,
+    ) -> Result<Vec<ApiResult>, Error> {
+        let query: Vec<_> = ty.map(|ty| ("type", ty.to_string())).into_iter().collect();
+        self.list_all(
+            &format!("{}/zones/{}/dns_records", self.base, zone_id),
+            &query,
+            PAGE_SIZE,
+        )
+        .await
+    }
+
+    /// Every result of the list at `url`, requesting `per_page` at a time.
+    async fn list_all<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &[(&str, String)],
+        per_page: usize,
+    ) -> Result<Vec<T>, Error> {
+        let mut results = Vec::new();
+
+        for page in 1.. {
+            let req = self
+                .http
+                .get(url)
+                .query(query)
+                .query(&[("page", page), ("per_page", per_page)]);
+            let listed: ApiMessage<Vec<T>> = send(req).await?;
+
+            let count = listed.result.as_ref().map_or(0, Vec::len);
+            results.extend(listed.result.unwrap_or_default());
+            let done = match listed.result_info {
+                Some(info) => info.is_last_page(),
+                None => true,
+            };
+            if done || count == 0 {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Number of records in the zone, read with a single request.
+    pub async fn count_records(&self, zone_id: &str) -> Result<usize, Error> {
+        let req = self
+            .http
+            .get(format!("{}/zones/{}/dns_records", self.base, zone_id))
+            .query(&[("per_page", 1)]);
+        let listed: ApiMessage<Vec<ApiResult>> = send(req).await?;
+        Ok(match listed.result_info {
+            Some(info) => info.total_count,
+            None => listed.result.map_or(0, |r| r.len()),
+        })
+    }
+
+    pub async fn create_record(
+        &self,
+        zone_id: &str,
+        record: &UpdateRecord,
+    ) -> Result<ApiResult, Error> {
+        let req = self
+            .http
+            .post(format!("{}/zones/{}/dns_records", self.base, zone_id))
+            .json(record);
+        result(send(req).await?)
+    }
+
+    /// Replaces the contents of the record `record_id`.
+    pub async fn update_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        record: &UpdateRecord,
+    ) -> Result<ApiResult, Error> {
+        let req = self
+            .http
+            .put(format!(
+                "{}/zones/{}/dns_records/{}",
+                self.base, zone_id, record_id
+            ))
+            .json(record);
+        result(send(req).await?)
+    }
+
+    pub async fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<(), Error> {
+        let req = self.http.delete(format!(
+            "{}/zones/{}/dns_records/{}",
+            self.base, zone_id, record_id
+        ));
+        send::<serde_json::Value>(req).await?;
+        Ok(())
+    }
+}
+
+/// The result of a successful response, which is expected to have one.
+fn result<T>(msg: ApiMessage<T>) -> Result<T, Error> {
+    msg.result.ok_or_else(|| {
+        Error::Api(vec![ApiError {
+            code: 0,
+            message: "response without a result".to_string(),
+        }])
+    })
+}
+
+/// Sends an API request and turns unsuccessful responses into errors.
+async fn send<T: DeserializeOwned>(req: reqwest::RequestBuilder) -> Result<ApiMessage<T>, Error> {
+    let resp = req.send().await?;
+    let status = resp.status();
+    let body = resp.text().await?;
+    let rejected =
+        status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN;
+
+    let msg: ApiMessage<T> = match serde_json::from_str(&body) {
+        Ok(msg) => msg,
+        Err(_) if rejected => return Err(Error::Auth(Vec::new())),
+        Err(e) => {
+            return Err(Error::Api(vec![ApiError {
+                code: status.as_u16().into(),
+                message: format!("unexpected response: {}", e),
+            }]))
+        }
+    };
+
+    if msg.success && msg.errors.is_empty() {
+        Ok(msg)
+    } else if rejected
+        || msg
+            .errors
+            .iter()
+            .any(|e| AUTH_ERROR_CODES.contains(&e.code))
+    {
+        Err(Error::Auth(msg.errors))
+    } else {
+        Err(Error::Api(msg.errors))
+    }
+}
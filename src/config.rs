@@ -0,0 +1,567 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+mod format;
+mod validate;
+
+pub use format::Format;
+use validate::{Problems, Source};
+
+use crate::{
+    cloudflare,
+    error::Error,
+    ip::{self, IpSource},
+    secret::Secret,
+    RecordType,
+};
+
+/// Used when no config file is given, the first that exists.
+const DEFAULT_PATHS: &[&str] = &[
+    "./config.yml",
+    "./config.yaml",
+    "./config.toml",
+    "./config.json",
+];
+/// Prefix of the environment variables that override the config file.
+const ENV_PREFIX: &str = "CF_DDNS_";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Shorthand for `auth: { token: ... }`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    api_token: Option<Secret>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth: Option<Auth>,
+    /// DNS services or accounts other than the one of the top level
+    /// credentials, by the name zones refer to them with.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub providers: BTreeMap<String, ProviderConfig>,
+    #[serde(default)]
+    pub zones: Vec<Zone>,
+    /// Shorthand for a config with a single zone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zone_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zone: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    subdomains: Vec<Subdomain>,
+    #[serde(default = "default_ttl")]
+    pub ttl: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    #[serde(default = "default_create_missing")]
+    pub create_missing: bool,
+    #[serde(default = "ip::default_sources")]
+    pub ip_sources: Vec<IpSource>,
+    #[serde(default = "default_ip_consensus")]
+    pub ip_consensus: usize,
+    /// Where to remember the records between runs, see `state::default_path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<PathBuf>,
+    /// Base URL of the Cloudflare API, e.g. to use a mock server.
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_path<'a>() -> Option<&'a Path> {
+    DEFAULT_PATHS
+        .iter()
+        .map(Path::new)
+        .find(|path| path.exists())
+}
+
+fn default_create_missing() -> bool {
+    true
+}
+
+fn default_ttl() -> usize {
+    1
+}
+
+fn default_api_url() -> String {
+    cloudflare::API_BASE.to_string()
+}
+
+fn default_ip_consensus() -> usize {
+    1
+}
+
+impl Config {
+    /// Loads the effective configuration: the defaults, overridden by the file
+    /// at `path` (or the first of `./config.{yml,yaml,toml,json}` that exists),
+    /// overridden by the `CF_DDNS_*` environment variables. The format of the
+    /// file is `format` or told by its extension. Every problem found is
+    /// reported at once.
+    pub fn load(path: Option<&Path>, format: Option<Format>) -> Result<Self, Error> {
+        let path = path.or_else(default_path);
+        let file = match path {
+            Some(path) => Some(Source::read(path, format)?),
+            None => None,
+        };
+        let mut problems = Problems::new(file);
+        let mut config = match problems.parse() {
+            Some(config) => config,
+            None => return Err(problems.into_error()),
+        };
+        config.apply_env(&mut problems);
+
+        if config.zone_id.is_some() || config.zone.is_some() {
+            problems.shorthand = true;
+            config.zones.insert(
+                0,
+                Zone {
+                    zone_id: config.zone_id.take(),
+                    name: config.zone.take(),
+                    proxied: false,
+                    ttl: None,
+                    provider: None,
+                    subdomains: std::mem::take(&mut config.subdomains),
+                },
+            );
+        } else if !config.subdomains.is_empty() {
+            problems.add(
+                "subdomains",
+                "subdomains are given without a zone_id or zone",
+            );
+        }
+
+        // The top level credentials are only needed by zones without a provider.
+        let given = resolve_auth(&mut config.api_token, &mut config.auth, "", &mut problems);
+        if !given && config.zones.iter().all(|zone| zone.provider.is_none()) {
+            problems.add("", "missing api_token or auth (or CF_DDNS_API_TOKEN)");
+        }
+        for (name, provider) in config.providers.iter_mut() {
+            match provider {
+                ProviderConfig::Cloudflare {
+                    api_token, auth, ..
+                } => {
+                    let prefix = format!("providers.{}.", name);
+                    if !resolve_auth(api_token, auth, &prefix, &mut problems) {
+                        problems.add(&format!("providers.{}", name), "missing api_token or auth");
+                    }
+                }
+                ProviderConfig::Rfc2136 { key_secret, .. } => {
+                    let path = format!("providers.{}.key_secret", name);
+                    match key_secret.resolve() {
+                        Ok(value) => {
+                            if let Err(e) = BASE64.decode(value) {
+                                problems.add(&path, format!("key_secret is not base64: {}", e));
+                            }
+                        }
+                        Err(e) => problems.add(&path, format!("could not read credentials: {}", e)),
+                    }
+                }
+                ProviderConfig::PowerDns { api_key, .. } => {
+                    if let Err(e) = api_key.resolve() {
+                        problems.add(
+                            &format!("providers.{}.api_key", name),
+                            format!("could not read credentials: {}", e),
+                        );
+                    }
+                }
+            }
+        }
+
+        config
+            .zones
+            .iter_mut()
+            .flat_map(|zone| zone.subdomains.iter_mut())
+            .for_each(|sd| {
+                if sd.name.is_empty() {
+                    sd.name = "@".to_string();
+                }
+            });
+        config.validate(&mut problems);
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(problems.into_error())
+        }
+    }
+
+    /// Overrides fields with the `CF_DDNS_*` environment variables that are set.
+    fn apply_env(&mut self, problems: &mut Problems) {
+        let token = env(problems, "API_TOKEN", &["api_token", "auth"], secret);
+        let email = env(problems, "AUTH_EMAIL", &["api_token", "auth"], text);
+        let key = match (
+            email,
+            env(problems, "AUTH_KEY", &["api_token", "auth"], secret),
+        ) {
+            (Some(email), Some(key)) => Some(Auth::Key { email, key }),
+            (None, None) => None,
+            _ => {
+                problems.add_env(
+                    "CF_DDNS_AUTH_EMAIL",
+                    "CF_DDNS_AUTH_EMAIL and CF_DDNS_AUTH_KEY must be set together",
+                );
+                None
+            }
+        };
+        match (token, key) {
+            (Some(_), Some(_)) => problems.add_env(
+                "CF_DDNS_API_TOKEN",
+                "set either CF_DDNS_API_TOKEN or CF_DDNS_AUTH_EMAIL and CF_DDNS_AUTH_KEY",
+            ),
+            (Some(token), None) => {
+                self.api_token = Some(token);
+                self.auth = None;
+            }
+            (None, Some(key)) => {
+                self.api_token = None;
+                self.auth = Some(key);
+            }
+            (None, None) => {}
+        }
+
+        if let Some(providers) = env(problems, "PROVIDERS", &["providers"], |v| {
+            serde_yaml::from_str(v)
+        }) {
+            self.providers = providers;
+        }
+        let zones = env(problems, "ZONES", &["zones"], |v| serde_yaml::from_str(v));
+        let zone_id = env(problems, "ZONE_ID", &["zones", "zone_id", "zone"], text);
+        let zone = env(problems, "ZONE", &["zones", "zone_id", "zone"], text);
+        if zone_id.is_some() && zone.is_some() {
+            problems.add_env(
+                "CF_DDNS_ZONE_ID",
+                "set either CF_DDNS_ZONE_ID or CF_DDNS_ZONE, not both",
+            );
+        }
+        if zone_id.is_some() || zone.is_some() {
+            if zones.is_some() {
+                problems.add_env(
+                    "CF_DDNS_ZONES",
+                    "CF_DDNS_ZONES cannot be combined with CF_DDNS_ZONE_ID or CF_DDNS_ZONE",
+                );
+            }
+            // A single zone from the environment replaces the zones of the file.
+            self.zones.clear();
+            self.zone_id = zone_id;
+            self.zone = zone;
+        }
+        if let Some(zones) = zones {
+            self.zones = zones;
+            self.zone_id = None;
+            self.zone = None;
+        }
+        if let Some(subdomains) = env(problems, "SUBDOMAINS", &["subdomains"], parse_subdomains) {
+            self.subdomains = subdomains;
+        }
+
+        if let Some(ttl) = env(problems, "TTL", &["ttl"], str::parse) {
+            self.ttl = ttl;
+        }
+        if let Some(interval) = env(problems, "INTERVAL", &["interval"], str::parse) {
+            self.interval = Some(interval);
+        }
+        if let Some(create_missing) =
+            env(problems, "CREATE_MISSING", &["create_missing"], str::parse)
+        {
+            self.create_missing = create_missing;
+        }
+        if let Some(ip_sources) = env(problems, "IP_SOURCES", &["ip_sources"], |v| {
+            serde_yaml::from_str(v)
+        }) {
+            self.ip_sources = ip_sources;
+        }
+        if let Some(ip_consensus) = env(problems, "IP_CONSENSUS", &["ip_consensus"], str::parse) {
+            self.ip_consensus = ip_consensus;
+        }
+        if let Some(state_file) = env(problems, "STATE_FILE", &["state_file"], text) {
+            self.state_file = Some(state_file.into());
+        }
+        if let Some(api_url) = env(problems, "API_URL", &["api_url"], text) {
+            self.api_url = api_url;
+        }
+        if let Some(url) = env(problems, "IP_URL", &["ip_sources"], text) {
+            self.ip_sources = vec![IpSource::Trace {
+                url: Some(url),
+                timeout: None,
+            }];
+        }
+    }
+
+    /// Translates the file at `path` to the format `to` without applying the
+    /// environment.
+    pub fn convert(path: Option<&Path>, from: Option<Format>, to: Format) -> Result<String, Error> {
+        let path = path
+            .or_else(default_path)
+            .ok_or_else(|| Error::Config("no config file to convert".to_string()))?;
+        let mut problems = Problems::new(Some(Source::read(path, from)?));
+        let config = match problems.parse() {
+            Some(config) if problems.is_empty() => config,
+            _ => return Err(problems.into_error()),
+        };
+        to.to_string(&config)
+            .map_err(|e| Error::Config(format!("cannot be written as {}: {}", to, e)))
+    }
+
+    /// A copy for printing, with literal credentials replaced by `<redacted>`.
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        config.auth = config.auth.as_ref().map(Auth::redacted);
+        for provider in config.providers.values_mut() {
+            match provider {
+                ProviderConfig::Cloudflare { auth, .. } => {
+                    *auth = auth.as_ref().map(Auth::redacted)
+                }
+                ProviderConfig::Rfc2136 { key_secret, .. } => *key_secret = key_secret.redacted(),
+                ProviderConfig::PowerDns { api_key, .. } => *api_key = api_key.redacted(),
+            }
+        }
+        config
+    }
+
+    /// The top level credentials, which `Config::load` requires if a zone
+    /// has no `provider`.
+    pub fn auth(&self) -> Option<&Auth> {
+        self.auth.as_ref()
+    }
+
+    /// Whether any zone manages records of type `ty`.
+    pub fn manages(&self, ty: RecordType) -> bool {
+        self.zones
+            .iter()
+            .flat_map(|zone| &zone.subdomains)
+            .any(|sd| sd.types.contains(&ty))
+    }
+}
+
+/// The value of `CF_DDNS_<name>` converted by `parse`, noting that it replaces
+/// the top level `fields` of the file. Empty variables count as unset, and
+/// invalid ones leave the fields of the file in place.
+fn env<T, E: fmt::Display>(
+    problems: &mut Problems,
+    name: &str,
+    fields: &[&'static str],
+    parse: impl FnOnce(&str) -> Result<T, E>,
+) -> Option<T> {
+    let var = format!("{}{}", ENV_PREFIX, name);
+    let value = std::env::var(&var).ok().filter(|v| !v.trim().is_empty())?;
+    match parse(value.trim()) {
+        Ok(value) => {
+            for field in fields {
+                problems.set_by_env(field, &var);
+            }
+            Some(value)
+        }
+        Err(e) => {
+            problems.add_env(&var, e);
+            None
+        }
+    }
+}
+
+fn text(value: &str) -> Result<String, Infallible> {
+    Ok(value.to_string())
+}
+
+fn secret(value: &str) -> Result<Secret, Infallible> {
+    Ok(Secret::from(value.to_string()))
+}
+
+/// Parses the compact form of `CF_DDNS_SUBDOMAINS`: comma separated names, each
+/// followed by colon separated options `proxied`, `dns-only`, `A`, `AAAA` or a
+/// TTL, e.g. `www:proxied,@:proxied:A:AAAA,vpn:dns-only:120`.
+fn parse_subdomains(value: &str) -> Result<Vec<Subdomain>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut parts = entry.split(':').map(str::trim);
+            let mut sd = Subdomain {
+                name: parts.next().unwrap_or_default().to_string(),
+                proxied: None,
+                types: Vec::new(),
+                ttl: None,
+            };
+            for option in parts {
+                match option.to_ascii_lowercase().as_str() {
+                    "proxied" => sd.proxied = Some(true),
+                    "dns-only" => sd.proxied = Some(false),
+                    "a" => sd.types.push(RecordType::A),
+                    "aaaa" => sd.types.push(RecordType::Aaaa),
+                    _ => {
+                        sd.ttl = Some(option.parse().map_err(|_| {
+                            format!(
+                                "unknown option {:?} for {}; expected proxied, dns-only, A, AAAA or a TTL",
+                                option, sd.name
+                            )
+                        })?)
+                    }
+                }
+            }
+            if sd.types.is_empty() {
+                sd.types = default_types();
+            }
+            Ok(sd)
+        })
+        .collect()
+}
+
+/// Turns the `api_token` shorthand into `auth` and reads the credentials,
+/// reporting problems at the fields under `prefix`. Returns whether any
+/// credentials were given.
+fn resolve_auth(
+    api_token: &mut Option<Secret>,
+    auth: &mut Option<Auth>,
+    prefix: &str,
+    problems: &mut Problems,
+) -> bool {
+    let path = if api_token.is_some() {
+        format!("{}api_token", prefix)
+    } else {
+        format!("{}auth", prefix)
+    };
+    match (api_token.take(), &auth) {
+        (Some(token), None) => *auth = Some(Auth::Token { token }),
+        (Some(_), Some(_)) => problems.add(&path, "give either api_token or auth, not both"),
+        (None, Some(_)) => {}
+        (None, None) => return false,
+    }
+    if let Err(e) = match auth.as_mut() {
+        Some(Auth::Token { token }) => token.resolve(),
+        Some(Auth::Key { key, .. }) => key.resolve(),
+        None => Ok(""),
+    } {
+        problems.add(&path, format!("could not read credentials: {}", e));
+    }
+    true
+}
+
+/// Credentials for the Cloudflare API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Auth {
+    /// A scoped API token.
+    Token { token: Secret },
+    /// The Global API Key of an account, sent with the account's email.
+    Key { email: String, key: Secret },
+}
+
+impl Auth {
+    fn redacted(&self) -> Auth {
+        match self {
+            Auth::Token { token } => Auth::Token {
+                token: token.redacted(),
+            },
+            Auth::Key { email, key } => Auth::Key {
+                email: email.clone(),
+                key: key.redacted(),
+            },
+        }
+    }
+}
+
+/// A DNS service or account that zones can refer to by name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ProviderConfig {
+    /// A Cloudflare account other than the one of the top level credentials.
+    Cloudflare {
+        /// Shorthand for `auth: { token: ... }`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_token: Option<Secret>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        auth: Option<Auth>,
+        /// Defaults to the top level `api_url`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_url: Option<String>,
+    },
+    /// A DNS server that accepts dynamic updates (RFC 2136) signed with an
+    /// HMAC-SHA256 TSIG key.
+    Rfc2136 {
+        /// Host name or address of the primary server, optionally with a port.
+        server: String,
+        /// Name of the key as configured on the server.
+        key_name: String,
+        /// The secret of the key, base64 encoded as in BIND's key files.
+        key_secret: Secret,
+    },
+    /// A PowerDNS Authoritative server, through its HTTP API.
+    PowerDns {
+        /// Where the webserver of PowerDNS listens, e.g. `http://127.0.0.1:8081`.
+        api_url: String,
+        /// The `api-key` of the server.
+        api_key: Secret,
+    },
+}
+
+impl ProviderConfig {
+    /// Whether zones are identified by a `zone_id` and records can be proxied.
+    pub fn is_cloudflare(&self) -> bool {
+        matches!(self, ProviderConfig::Cloudflare { .. })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Zone {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_id: Option<String>,
+    /// Domain name to look up the zone id by, instead of giving `zone_id`.
+    #[serde(rename = "zone", default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Default for subdomains that do not set `proxied`.
+    #[serde(default)]
+    pub proxied: bool,
+    /// Default for subdomains that do not set `ttl`, falling back to the global one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<usize>,
+    /// Name of the entry of `providers` that hosts the zone, instead of the
+    /// Cloudflare account of the top level credentials.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    pub subdomains: Vec<Subdomain>,
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.name, &self.zone_id) {
+            (Some(name), _) => write!(f, "{}", name),
+            (None, Some(id)) => write!(f, "{}", id),
+            (None, None) => write!(f, "<unnamed>"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subdomain {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    #[serde(default = "default_types")]
+    pub types: Vec<RecordType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<usize>,
+}
+
+impl Subdomain {
+    pub fn proxied(&self, zone: &Zone) -> bool {
+        self.proxied.unwrap_or(zone.proxied)
+    }
+
+    /// TTL to publish, falling back to the zone and then `default`. Proxied
+    /// records always use automatic.
+    pub fn ttl(&self, zone: &Zone, default: usize) -> usize {
+        if self.proxied(zone) {
+            1
+        } else {
+            self.ttl.or(zone.ttl).unwrap_or(default)
+        }
+    }
+}
+
+fn default_types() -> Vec<RecordType> {
+    vec![RecordType::A]
+}
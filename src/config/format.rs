@@ -0,0 +1,206 @@
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess,
+    Visitor,
+};
+use serde::Serialize;
+use serde_yaml::Value;
+
+use std::{fmt, path::Path};
+
+/// A format the config file can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Yaml,
+    Toml,
+    Json,
+}
+
+/// A parse error and, if known, its line and column.
+pub struct FormatError {
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl FormatError {
+    fn yaml(e: serde_yaml::Error) -> Self {
+        let message = e.to_string();
+        FormatError {
+            position: e.location().map(|loc| (loc.line(), loc.column())),
+            // The position is reported separately.
+            message: match message.rfind(" at line ") {
+                Some(i) => message[..i].to_string(),
+                None => message,
+            },
+        }
+    }
+
+    fn json(e: serde_json::Error) -> Self {
+        let message = e.to_string();
+        FormatError {
+            position: (e.line() > 0).then(|| (e.line(), e.column())),
+            message: match message.rfind(" at line ") {
+                Some(i) => message[..i].to_string(),
+                None => message,
+            },
+        }
+    }
+
+    fn toml(e: toml::de::Error, text: &str) -> Self {
+        FormatError {
+            position: e.span().map(|span| line_column(text, span.start)),
+            message: e.message().to_string(),
+        }
+    }
+}
+
+/// 1-based line and column of the byte at `index`.
+fn line_column(text: &str, index: usize) -> (usize, usize) {
+    let before = &text[..index.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+impl Format {
+    /// The format of `path` by its extension. Files without a known extension
+    /// are read as YAML.
+    pub fn of(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("json") => Format::Json,
+            _ => Format::Yaml,
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(self, text: &str) -> Result<T, FormatError> {
+        match self {
+            Format::Yaml => serde_yaml::from_str(text).map_err(FormatError::yaml),
+            Format::Toml => toml::from_str(text).map_err(|e| FormatError::toml(e, text)),
+            Format::Json => serde_json::from_str(text).map_err(FormatError::json),
+        }
+    }
+
+    pub fn to_string<T: Serialize>(self, value: &T) -> Result<String, String> {
+        match self {
+            Format::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+            Format::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+            Format::Json => serde_json::to_string_pretty(value)
+                .map(|json| json + "\n")
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Line and column of the value at the dot separated `path` in `text`.
+    pub fn locate(self, text: &str, path: &str) -> Option<(usize, usize)> {
+        let parts: Vec<_> = path.split('.').filter(|p| !p.is_empty()).collect();
+        let seed = Locate(&parts);
+        let e = match self {
+            Format::Yaml => seed
+                .deserialize(serde_yaml::Deserializer::from_str(text))
+                .map_err(FormatError::yaml),
+            Format::Toml => seed
+                .deserialize(toml::Deserializer::new(text))
+                .map_err(|e| FormatError::toml(e, text)),
+            Format::Json => seed
+                .deserialize(&mut serde_json::Deserializer::from_str(text))
+                .map_err(FormatError::json),
+        }
+        .err()?;
+        e.message.contains(LOCATED).then_some(e.position)?
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Yaml => write!(f, "YAML"),
+            Format::Toml => write!(f, "TOML"),
+            Format::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+/// Marks the error raised at the value being located.
+const LOCATED: &str = "located the value";
+
+/// Walks the document along a path and fails at the value it leads to, so
+/// that the error carries the position of that value.
+struct Locate<'a>(&'a [&'a str]);
+
+impl<'de> DeserializeSeed<'de> for Locate<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+        d.deserialize_any(self)
+    }
+}
+
+/// Fails on any value except mappings and sequences that lead further down the path.
+macro_rules! located {
+    ($($method:ident($($ty:ty)?)),*) => {
+        $(
+            fn $method<E: de::Error>(self $(, _: $ty)?) -> Result<(), E> {
+                Err(E::custom(LOCATED))
+            }
+        )*
+    };
+}
+
+impl<'de> Visitor<'de> for Locate<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "any value")
+    }
+
+    located!(
+        visit_bool(bool),
+        visit_i64(i64),
+        visit_u64(u64),
+        visit_i128(i128),
+        visit_u128(u128),
+        visit_f64(f64),
+        visit_str(&str),
+        visit_bytes(&[u8]),
+        visit_unit(),
+        visit_none()
+    );
+
+    fn visit_some<D: Deserializer<'de>>(self, _: D) -> Result<(), D::Error> {
+        Err(de::Error::custom(LOCATED))
+    }
+
+    fn visit_enum<A: de::EnumAccess<'de>>(self, _: A) -> Result<(), A::Error> {
+        Err(de::Error::custom(LOCATED))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Some((next, rest)) = self.0.split_first() else {
+            return Err(de::Error::custom(LOCATED));
+        };
+        while let Some(key) = map.next_key::<Value>()? {
+            if key.as_str() == Some(next) {
+                return map.next_value_seed(Locate(rest));
+            }
+            map.next_value::<IgnoredAny>()?;
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let Some((next, rest)) = self.0.split_first() else {
+            return Err(de::Error::custom(LOCATED));
+        };
+        let Ok(index) = next.parse::<usize>() else {
+            return Ok(());
+        };
+        for _ in 0..index {
+            if seq.next_element::<IgnoredAny>()?.is_none() {
+                return Ok(());
+            }
+        }
+        seq.next_element_seed(Locate(rest)).map(|_| ())
+    }
+}
@@ -0,0 +1,542 @@
+use serde::de::{self, value, Deserializer, Visitor};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+};
+
+use super::{
+    format::{Format, FormatError},
+    Config, ProviderConfig, Subdomain, Zone,
+};
+use crate::error::Error;
+
+const TTL_RANGE: &str = "must be 1 (automatic) or between 60 and 86400";
+/// Shortest interval between checks, so that the daemon does not flood the
+/// ip sources and the API.
+const MIN_INTERVAL: u64 = 30;
+
+fn valid_ttl(ttl: usize) -> bool {
+    ttl == 1 || (60..=86400).contains(&ttl)
+}
+
+/// A problem with the configuration and where it was found: a position in the
+/// file, an environment variable, or the setting itself.
+struct Problem {
+    at: String,
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.at, self.message)
+    }
+}
+
+/// A config file that has been read.
+pub struct Source {
+    pub name: String,
+    pub text: String,
+    pub format: Format,
+}
+
+impl Source {
+    /// Reads the file at `path`, in `format` or the one its extension tells.
+    pub fn read(path: &Path, format: Option<Format>) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        Ok(Source {
+            name: path.display().to_string(),
+            text,
+            format: format.unwrap_or_else(|| Format::of(path)),
+        })
+    }
+}
+
+/// Collects the problems of a configuration, translating the dot separated
+/// paths of settings in the effective config to where they were given.
+pub struct Problems {
+    file: Option<Source>,
+    /// Whether the first zone was given at the top level of the file.
+    pub shorthand: bool,
+    /// The environment variables that replaced top level fields of the file.
+    env: HashMap<&'static str, String>,
+    problems: Vec<Problem>,
+}
+
+impl Problems {
+    pub fn new(file: Option<Source>) -> Self {
+        Problems {
+            file,
+            shorthand: false,
+            env: HashMap::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn set_by_env(&mut self, field: &'static str, var: &str) {
+        self.env.insert(field, var.to_string());
+    }
+
+    /// Reports a problem with the setting at `path` of the effective config.
+    pub fn add(&mut self, path: &str, message: impl fmt::Display) {
+        let path = self.file_path(path);
+        let root = path.split('.').next().unwrap_or_default();
+        let at = match self.env.get(root) {
+            Some(var) => var.clone(),
+            None => self.locate(&path),
+        };
+        self.problems.push(Problem {
+            at,
+            message: message.to_string(),
+        });
+    }
+
+    pub fn add_env(&mut self, var: &str, message: impl fmt::Display) {
+        self.problems.push(Problem {
+            at: var.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    pub fn into_error(self) -> Error {
+        match &self.problems[..] {
+            [problem] => Error::Config(problem.to_string()),
+            problems => Error::Config(format!(
+                "{} problems\n{}",
+                problems.len(),
+                problems
+                    .iter()
+                    .map(|p| format!("  {}", p))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )),
+        }
+    }
+
+    /// Path in the file of the setting at `path` of the effective config,
+    /// where the first zone may have been given at the top level.
+    fn file_path(&self, path: &str) -> String {
+        let mut parts = path.splitn(3, '.');
+        let (Some("zones"), Some(index), rest) = (parts.next(), parts.next(), parts.next()) else {
+            return path.to_string();
+        };
+        match index.parse::<usize>() {
+            Ok(0) if self.shorthand => rest.unwrap_or_default().to_string(),
+            Ok(i) if self.shorthand => match rest {
+                Some(rest) => format!("zones.{}.{}", i - 1, rest),
+                None => format!("zones.{}", i - 1),
+            },
+            _ => path.to_string(),
+        }
+    }
+
+    /// `file:line:column` of the setting at `path` in the file, or the path if
+    /// the file does not give it.
+    fn locate(&self, path: &str) -> String {
+        let file = match &self.file {
+            Some(file) => file,
+            None if path.is_empty() => return "configuration".to_string(),
+            None => return path.to_string(),
+        };
+        match file.format.locate(&file.text, path) {
+            Some((line, column)) => format!("{}:{}:{}", file.name, line, column),
+            None if path.is_empty() => file.name.clone(),
+            None => format!("{}: {}", file.name, path),
+        }
+    }
+
+    fn add_parse_error(&mut self, e: FormatError) {
+        let name = self.file.as_ref().map_or("", |file| &file.name);
+        let at = match e.position {
+            Some((line, column)) => format!("{}:{}:{}", name, line, column),
+            None => name.to_string(),
+        };
+        self.problems.push(Problem {
+            at,
+            message: e.message,
+        });
+    }
+
+    /// Reads the file, reporting syntax errors, every unknown field and the first
+    /// invalid value. Unknown fields are left out of the config returned, so
+    /// that the rest of it can still be checked. Without a file, every setting
+    /// has its default.
+    pub fn parse(&mut self) -> Option<Config> {
+        let Some(file) = &self.file else {
+            return Some(serde_yaml::from_str("{}").expect("every field has a default"));
+        };
+        let (format, text) = (file.format, file.text.clone());
+        let mut value: Value = match format.parse(&text) {
+            Ok(value) => value,
+            Err(e) => {
+                self.add_parse_error(e);
+                return None;
+            }
+        };
+
+        let mut unknown = self.strip_unknown_fields::<Config>(&mut value, "");
+        if let Some(zones) = value.get_mut("zones").and_then(Value::as_sequence_mut) {
+            for (i, zone) in zones.iter_mut().enumerate() {
+                unknown |= self.strip_unknown_fields::<Zone>(zone, &format!("zones.{}", i));
+                unknown |= self.strip_unknown_subdomain_fields(zone, &format!("zones.{}.", i));
+            }
+        }
+        unknown |= self.strip_unknown_subdomain_fields(&mut value, "");
+
+        if !unknown {
+            return match format.parse(&text) {
+                Ok(config) => Some(config),
+                Err(e) => {
+                    self.add_parse_error(e);
+                    None
+                }
+            };
+        }
+        match serde_yaml::from_value(value) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                // The file gives the position of the invalid value, unless it
+                // stops at one of the unknown fields first.
+                match format.parse::<Config>(&text) {
+                    Err(e) if !e.message.contains("unknown field") => self.add_parse_error(e),
+                    _ => self.add_parse_error(FormatError {
+                        position: None,
+                        message: e.to_string(),
+                    }),
+                }
+                None
+            }
+        }
+    }
+
+    fn strip_unknown_subdomain_fields(&mut self, parent: &mut Value, prefix: &str) -> bool {
+        let mut unknown = false;
+        let subdomains = parent
+            .get_mut("subdomains")
+            .and_then(Value::as_sequence_mut);
+        for (i, sd) in subdomains.into_iter().flatten().enumerate() {
+            let path = format!("{}subdomains.{}", prefix, i);
+            unknown |= self.strip_unknown_fields::<Subdomain>(sd, &path);
+        }
+        unknown
+    }
+
+    /// Reports and removes the keys of the mapping at `path` that `T` does
+    /// not have. Returns whether there were any.
+    fn strip_unknown_fields<'de, T: Deserialize<'de>>(
+        &mut self,
+        value: &mut Value,
+        path: &str,
+    ) -> bool {
+        let Some(map) = value.as_mapping_mut() else {
+            return false;
+        };
+        let fields = field_names::<T>();
+        let unknown: Vec<_> = map
+            .keys()
+            .filter(|key| key.as_str().is_some_and(|key| !fields.contains(&key)))
+            .cloned()
+            .collect();
+        for key in &unknown {
+            let name = key.as_str().unwrap_or_default();
+            let at = self.locate(&format!("{}.{}", path, name));
+            let expected = fields
+                .iter()
+                .map(|f| format!("`{}`", f))
+                .collect::<Vec<_>>()
+                .join(", ");
+            self.problems.push(Problem {
+                at,
+                message: format!("unknown field `{}`, expected one of {}", name, expected),
+            });
+            map.remove(key);
+        }
+        !unknown.is_empty()
+    }
+}
+
+impl Config {
+    /// Checks the settings that cannot be expressed by their types.
+    pub(super) fn validate(&self, problems: &mut Problems) {
+        if self.zones.is_empty() {
+            problems.add("zones", "no zones configured");
+        }
+        if !valid_ttl(self.ttl) {
+            problems.add("ttl", format!("invalid ttl {}: {}", self.ttl, TTL_RANGE));
+        }
+        if let Some(interval) = self.interval.filter(|i| *i < MIN_INTERVAL) {
+            problems.add(
+                "interval",
+                format!(
+                    "interval {} is too short: must be at least {} seconds",
+                    interval, MIN_INTERVAL
+                ),
+            );
+        }
+        if self.ip_consensus == 0 || self.ip_consensus > self.ip_sources.len() {
+            problems.add(
+                "ip_consensus",
+                format!(
+                    "ip_consensus {} must be between 1 and the number of ip_sources ({})",
+                    self.ip_consensus,
+                    self.ip_sources.len()
+                ),
+            );
+        }
+
+        if let Err(e) = reqwest::Url::parse(&self.api_url) {
+            problems.add(
+                "api_url",
+                format!("invalid api_url {:?}: {}", self.api_url, e),
+            );
+        }
+        for (name, provider) in &self.providers {
+            match provider {
+                ProviderConfig::Cloudflare {
+                    api_url: Some(url), ..
+                }
+                | ProviderConfig::PowerDns { api_url: url, .. } => {
+                    if let Err(e) = reqwest::Url::parse(url) {
+                        problems.add(
+                            &format!("providers.{}.api_url", name),
+                            format!("invalid api_url {:?}: {}", url, e),
+                        );
+                    }
+                }
+                ProviderConfig::Cloudflare { api_url: None, .. } => {}
+                ProviderConfig::Rfc2136 {
+                    server, key_name, ..
+                } => {
+                    if server.trim().is_empty() {
+                        problems.add(&format!("providers.{}.server", name), "server is empty");
+                    }
+                    if let Err(e) = check_hostname(key_name.trim_end_matches('.'), false) {
+                        problems.add(
+                            &format!("providers.{}.key_name", name),
+                            format!("invalid key_name {:?}: {}", key_name, e),
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut zones = HashSet::new();
+        for (i, zone) in self.zones.iter().enumerate() {
+            let path = format!("zones.{}", i);
+            match (&zone.zone_id, &zone.name) {
+                (Some(_), Some(_)) => problems.add(
+                    &path,
+                    format!(
+                        "zone {} must have either a zone_id or a zone name, not both",
+                        zone
+                    ),
+                ),
+                (None, None) => problems.add(&path, "zone must have a zone_id or a zone name"),
+                (None, Some(name)) => {
+                    if let Err(e) = check_hostname(name.trim_end_matches('.'), false) {
+                        problems.add(
+                            &format!("{}.zone", path),
+                            format!("invalid zone {:?}: {}", name, e),
+                        );
+                    }
+                }
+                (Some(_), None) => {}
+            }
+            if !zones.insert(zone.to_string().trim_end_matches('.').to_ascii_lowercase()) {
+                let field = if zone.name.is_some() {
+                    "zone"
+                } else {
+                    "zone_id"
+                };
+                problems.add(
+                    &format!("{}.{}", path, field),
+                    format!("zone {} is listed more than once", zone),
+                );
+            }
+            if let Some(provider) = zone
+                .provider
+                .as_ref()
+                .filter(|p| !self.providers.contains_key(*p))
+            {
+                let known = self
+                    .providers
+                    .keys()
+                    .map(|name| format!("`{}`", name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                problems.add(
+                    &format!("{}.provider", path),
+                    if known.is_empty() {
+                        format!(
+                            "unknown provider `{}`, no providers are configured",
+                            provider
+                        )
+                    } else {
+                        format!("unknown provider `{}`, expected one of {}", provider, known)
+                    },
+                );
+            }
+            if let Some(ttl) = zone.ttl.filter(|ttl| !valid_ttl(*ttl)) {
+                problems.add(
+                    &format!("{}.ttl", path),
+                    format!("invalid ttl {} for zone {}: {}", ttl, zone, TTL_RANGE),
+                );
+            }
+            // Other providers only know zones by name and cannot proxy traffic.
+            let cloudflare = zone
+                .provider
+                .as_ref()
+                .and_then(|p| self.providers.get(p))
+                .is_none_or(ProviderConfig::is_cloudflare);
+            if !cloudflare && zone.zone_id.is_some() {
+                problems.add(
+                    &format!("{}.zone_id", path),
+                    format!(
+                        "zone_id is only known to Cloudflare; give the name of zone {} with zone",
+                        zone
+                    ),
+                );
+            }
+            if !cloudflare && zone.proxied {
+                problems.add(
+                    &format!("{}.proxied", path),
+                    format!(
+                        "records of zone {} cannot be proxied outside Cloudflare",
+                        zone
+                    ),
+                );
+            }
+
+            let mut names = HashSet::new();
+            for (j, sd) in zone.subdomains.iter().enumerate() {
+                let path = format!("{}.subdomains.{}", path, j);
+                let name_path = format!("{}.name", path);
+                let zone_name = zone.name.as_deref().map(|n| n.trim_end_matches('.'));
+                if let Err(e) = check_hostname(&sd.name, true) {
+                    problems.add(&name_path, format!("invalid name {:?}: {}", sd.name, e));
+                } else if let Some(relative) = zone_name.and_then(|z| strip_zone(&sd.name, z)) {
+                    problems.add(
+                        &name_path,
+                        format!(
+                            "{} includes the zone {}; use {:?} instead",
+                            sd.name, zone, relative
+                        ),
+                    );
+                }
+                if !names.insert(sd.name.to_ascii_lowercase()) {
+                    problems.add(
+                        &name_path,
+                        format!(
+                            "{} is listed more than once in zone {}; give all its types in one entry",
+                            sd.name, zone
+                        ),
+                    );
+                }
+                if sd.types.is_empty() {
+                    problems.add(
+                        &format!("{}.types", path),
+                        format!("no types given for {}", sd.name),
+                    );
+                }
+                if !cloudflare && sd.proxied == Some(true) {
+                    problems.add(
+                        &format!("{}.proxied", path),
+                        format!("{} cannot be proxied outside Cloudflare", sd.name),
+                    );
+                }
+                if let Some(ttl) = sd.ttl.filter(|ttl| !valid_ttl(*ttl)) {
+                    problems.add(
+                        &format!("{}.ttl", path),
+                        format!("invalid ttl {} for {}: {}", ttl, sd.name, TTL_RANGE),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Checks that `name` is a valid DNS name. Subdomains may also be `@` for the
+/// apex and start with a `*` label for wildcard records.
+fn check_hostname(name: &str, subdomain: bool) -> Result<(), String> {
+    if subdomain && name == "@" {
+        return Ok(());
+    }
+    if name.len() > 253 {
+        return Err("longer than 253 characters".to_string());
+    }
+    for (i, label) in name.split('.').enumerate() {
+        if subdomain && i == 0 && label == "*" {
+            continue;
+        }
+        if label.is_empty() {
+            return Err("empty label".to_string());
+        }
+        if label.len() > 63 {
+            return Err(format!("label {} is longer than 63 characters", label));
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+        {
+            return Err(format!("{:?} is not allowed", c));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("label {} starts or ends with a hyphen", label));
+        }
+    }
+    Ok(())
+}
+
+/// `name` relative to `zone` if it is given as a fully qualified name.
+fn strip_zone(name: &str, zone: &str) -> Option<String> {
+    if name.eq_ignore_ascii_case(zone) {
+        return Some("@".to_string());
+    }
+    let split = name.len().checked_sub(zone.len() + 1)?;
+    let (relative, suffix) = name.split_at_checked(split)?;
+    (suffix.starts_with('.') && suffix[1..].eq_ignore_ascii_case(zone))
+        .then(|| relative.to_string())
+}
+
+/// Names of the fields `T` has in the config, as passed by its `Deserialize`
+/// implementation to the deserializer.
+fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct Fields<'a>(&'a Cell<&'static [&'static str]>);
+
+    impl<'de> Deserializer<'de> for Fields<'_> {
+        type Error = value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0.set(fields);
+            Err(de::Error::custom("fields recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+            byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map
+            enum identifier ignored_any
+        }
+    }
+
+    let fields = Cell::new(&[][..]);
+    let _ = T::deserialize(Fields(&fields));
+    fields.get()
+}
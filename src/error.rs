@@ -0,0 +1,90 @@
+use std::{fmt, process::ExitCode};
+
+use crate::ApiError;
+
+/// Everything that can make a run fail.
+///
+/// Each variant maps to its own process exit code:
+///
+/// | Code | Variant       | Meaning                                        |
+/// |------|---------------|------------------------------------------------|
+/// | 0    |               | All records are up to date                     |
+/// | 1    | `Io`          | Unexpected local error                         |
+/// | 2    | `Config`      | The configuration could not be loaded          |
+/// | 3    | `IpDetection` | No public address could be detected            |
+/// | 4    | `Auth`        | The DNS provider rejected the credentials      |
+/// | 5    | `Api`         | The DNS provider rejected a request            |
+/// | 6    | `Network`     | The DNS provider could not be reached          |
+/// | 7    | `Partial`     | Some records were updated, others failed       |
+/// | 64   |               | The command line is invalid                    |
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("Could not detect public address: {0}")]
+    IpDetection(String),
+    #[error("Authentication failed: {}", ApiErrors(.0))]
+    Auth(Vec<ApiError>),
+    #[error("API error: {}", ApiErrors(.0))]
+    Api(Vec<ApiError>),
+    #[error("Network error: {0}")]
+    Network(Box<dyn std::error::Error + Send + Sync>),
+    #[error("{failed} of {total} records failed to update")]
+    Partial { failed: usize, total: usize },
+}
+
+impl Error {
+    /// A copy to report the same failure more than once. Errors of other
+    /// libraries are only kept as their message.
+    pub fn duplicate(&self) -> Error {
+        match self {
+            Error::Io(e) => Error::Io(std::io::Error::new(e.kind(), e.to_string())),
+            Error::Config(message) => Error::Config(message.clone()),
+            Error::IpDetection(message) => Error::IpDetection(message.clone()),
+            Error::Auth(errors) => Error::Auth(errors.clone()),
+            Error::Api(errors) => Error::Api(errors.clone()),
+            Error::Network(e) => Error::Network(e.to_string().into()),
+            Error::Partial { failed, total } => Error::Partial {
+                failed: *failed,
+                total: *total,
+            },
+        }
+    }
+
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Error::Io(_) => 1,
+            Error::Config(_) => 2,
+            Error::IpDetection(_) => 3,
+            Error::Auth(_) => 4,
+            Error::Api(_) => 5,
+            Error::Network(_) => 6,
+            Error::Partial { .. } => 7,
+        })
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Network(Box::new(e))
+    }
+}
+
+struct ApiErrors<'a>(&'a [ApiError]);
+
+impl fmt::Display for ApiErrors<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "no details given");
+        }
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{} (code {})", e.message, e.code)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use crate::{error::Error, RecordType};
+
+const TRACE_V4: &str = "https://1.1.1.1/cdn-cgi/trace";
+const TRACE_V6: &str = "https://[2606:4700:4700::1111]/cdn-cgi/trace";
+const DEFAULT_SOURCE_TIMEOUT: u64 = 10;
+
+/// A service that reports the public address of the host.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IpSource {
+    /// Cloudflare's `/cdn-cgi/trace` endpoint, or another `url` answering in
+    /// its format.
+    Trace {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout: Option<u64>,
+    },
+    /// A URL that answers with the bare address.
+    Text {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout: Option<u64>,
+    },
+    /// A URL that answers with JSON holding the address at the dot separated `field`.
+    Json {
+        url: String,
+        field: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout: Option<u64>,
+    },
+    /// An address assigned to the local network interface `name`. IPv6 needs
+    /// Linux, to leave out temporary addresses.
+    Interface { name: String },
+}
+
+pub fn default_sources() -> Vec<IpSource> {
+    vec![IpSource::Trace {
+        url: None,
+        timeout: None,
+    }]
+}
+
+impl IpSource {
+    fn timeout(&self) -> Duration {
+        let secs = match self {
+            IpSource::Trace { timeout, .. }
+            | IpSource::Text { timeout, .. }
+            | IpSource::Json { timeout, .. } => timeout,
+            IpSource::Interface { .. } => &None,
+        };
+        Duration::from_secs(secs.unwrap_or(DEFAULT_SOURCE_TIMEOUT))
+    }
+
+    async fn fetch(&self, client: &reqwest::Client, ty: RecordType) -> Result<IpAddr, String> {
+        let url = match (self, ty) {
+            (IpSource::Trace { url: Some(url), .. }, _) => url,
+            (IpSource::Trace { .. }, RecordType::A) => TRACE_V4,
+            (IpSource::Trace { .. }, RecordType::Aaaa) => TRACE_V6,
+            (IpSource::Text { url, .. } | IpSource::Json { url, .. }, _) => url,
+            (IpSource::Interface { name }, _) => return interface_ip(name, ty),
+        };
+
+        let body = async {
+            client
+                .get(url)
+                .timeout(self.timeout())
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let ip = match self {
+            IpSource::Trace { .. } => body
+                .split_ascii_whitespace()
+                .find_map(|s| match s.split_once('=') {
+                    Some(("ip", x)) => Some(x.to_string()),
+                    _ => None,
+                })
+                .ok_or("no ip found")?,
+            IpSource::Text { .. } => body.trim().to_string(),
+            IpSource::Json { field, .. } => {
+                let json: serde_json::Value =
+                    serde_json::from_str(&body).map_err(|e| e.to_string())?;
+                json_field(&json, field)
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| format!("no string at {}", field))?
+                    .to_string()
+            }
+            IpSource::Interface { .. } => unreachable!(),
+        };
+
+        let ip: IpAddr = ip
+            .parse()
+            .map_err(|_| format!("invalid address {:?}", ip))?;
+        match (ty, ip) {
+            (RecordType::A, IpAddr::V4(_)) | (RecordType::Aaaa, IpAddr::V6(_)) => Ok(ip),
+            _ => Err(format!("got {}", ip)),
+        }
+    }
+}
+
+impl fmt::Display for IpSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpSource::Trace { url: None, .. } => write!(f, "trace"),
+            IpSource::Trace { url: Some(url), .. } => write!(f, "{}", url),
+            IpSource::Text { url, .. } | IpSource::Json { url, .. } => write!(f, "{}", url),
+            IpSource::Interface { name } => write!(f, "interface {}", name),
+        }
+    }
+}
+
+/// Looks up a dot separated path such as `data.0.ip`.
+fn json_field<'a>(json: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.').try_fold(json, |v, key| match v {
+        serde_json::Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        _ => v.get(key),
+    })
+}
+
+/// Picks the public address of family `ty` on interface `name`, preferring
+/// permanent over dynamically configured addresses.
+fn interface_ip(name: &str, ty: RecordType) -> Result<IpAddr, String> {
+    let interfaces = if_addrs::get_if_addrs().map_err(|e| e.to_string())?;
+    if !interfaces.iter().any(|i| i.name == name) {
+        return Err("no such interface".to_string());
+    }
+    // Without the flags, temporary addresses cannot be told apart.
+    let flags = match ty {
+        RecordType::A => HashMap::new(),
+        RecordType::Aaaa => ipv6_flags(name)?,
+    };
+
+    interfaces
+        .iter()
+        .filter(|i| i.name == name)
+        .map(|i| i.ip())
+        .filter(|ip| match (ty, ip) {
+            (RecordType::A, IpAddr::V4(ip)) => is_public_v4(ip),
+            (RecordType::Aaaa, IpAddr::V6(ip)) => {
+                is_public_v6(ip) && flags.get(ip).is_none_or(|f| f & IFA_F_UNSTABLE == 0)
+            }
+            _ => false,
+        })
+        .min_by_key(|ip| match ip {
+            IpAddr::V6(ip) => flags.get(ip).is_none_or(|f| f & IFA_F_PERMANENT == 0),
+            IpAddr::V4(_) => false,
+        })
+        .ok_or_else(|| format!("no public {} address", ty.family()))
+}
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || shared)
+}
+
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    let link_local = (ip.segments()[0] & 0xffc0) == 0xfe80;
+    let unique_local = (ip.segments()[0] & 0xfe00) == 0xfc00;
+    !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || link_local || unique_local)
+}
+
+const IFA_F_TEMPORARY: u32 = 0x01;
+const IFA_F_DADFAILED: u32 = 0x08;
+const IFA_F_DEPRECATED: u32 = 0x20;
+const IFA_F_TENTATIVE: u32 = 0x40;
+const IFA_F_PERMANENT: u32 = 0x80;
+/// Addresses that should not be published: privacy extensions and addresses
+/// that are being phased out or not usable yet.
+const IFA_F_UNSTABLE: u32 = IFA_F_TEMPORARY | IFA_F_DADFAILED | IFA_F_DEPRECATED | IFA_F_TENTATIVE;
+
+/// Kernel flags of the IPv6 addresses on `name`, read from `/proc/net/if_inet6`,
+/// which only Linux has.
+fn ipv6_flags(name: &str) -> Result<HashMap<Ipv6Addr, u32>, String> {
+    let table = std::fs::read_to_string("/proc/net/if_inet6").map_err(|e| {
+        format!(
+            "cannot tell temporary IPv6 addresses apart without /proc/net/if_inet6: {}",
+            e
+        )
+    })?;
+    Ok(table
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            match fields[..] {
+                [addr, _, _, _, flags, dev] if dev == name && addr.len() == 32 => {
+                    let addr = u128::from_str_radix(addr, 16).ok()?;
+                    let flags = u32::from_str_radix(flags, 16).ok()?;
+                    Some((Ipv6Addr::from(addr), flags))
+                }
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+/// Detects public addresses by asking the configured sources in order.
+pub struct Detector {
+    v4: reqwest::Client,
+    v6: reqwest::Client,
+    sources: Vec<IpSource>,
+    consensus: usize,
+}
+
+impl Detector {
+    /// Sources are queried over family specific clients so that a URL serving
+    /// both families reports the address of the family being asked for.
+    pub fn new(sources: Vec<IpSource>, consensus: usize) -> Result<Self, Error> {
+        let client = |local: IpAddr| reqwest::Client::builder().local_address(local).build();
+        Ok(Detector {
+            v4: client(Ipv4Addr::UNSPECIFIED.into())?,
+            v6: client(Ipv6Addr::UNSPECIFIED.into())?,
+            sources,
+            consensus,
+        })
+    }
+
+    /// Returns the first address reported by `consensus` sources.
+    pub async fn get_ip(&self, ty: RecordType) -> Result<IpAddr, Error> {
+        let client = match ty {
+            RecordType::A => &self.v4,
+            RecordType::Aaaa => &self.v6,
+        };
+
+        let mut votes: HashMap<IpAddr, usize> = HashMap::new();
+        for source in &self.sources {
+            match source.fetch(client, ty).await {
+                Ok(ip) => {
+                    debug!("{} source {} reported {}", ty.family(), source, ip);
+                    let count = votes.entry(ip).or_default();
+                    *count += 1;
+                    if *count >= self.consensus {
+                        return Ok(ip);
+                    }
+                }
+                Err(e) => warn!("{} source {} failed: {}", ty.family(), source, e),
+            }
+        }
+
+        let reported = votes
+            .iter()
+            .map(|(ip, n)| format!("{} ({}x)", ip, n))
+            .collect::<Vec<_>>();
+        Err(Error::IpDetection(
+            if self.consensus > 1 && !reported.is_empty() {
+                format!(
+                    "{}: no address reported by {} sources, got {}",
+                    ty.family(),
+                    self.consensus,
+                    reported.join(", ")
+                )
+            } else {
+                format!("{}: all sources failed", ty.family())
+            },
+        ))
+    }
+}
@@ -0,0 +1,63 @@
+//! Keeps Cloudflare DNS records pointed at the public address of a host.
+//!
+//! [`CloudflareClient`] covers the parts of the Cloudflare API needed to look
+//! up and change DNS records, and [`update::check`] brings the records of a
+//! [`config::Config`] up to date through the [`DnsProvider`] of each zone.
+
+#[macro_use]
+pub mod log;
+pub mod cloudflare;
+pub mod config;
+pub mod error;
+pub mod ip;
+pub mod provider;
+pub mod secret;
+pub mod state;
+pub mod update;
+pub mod verify;
+
+pub use cloudflare::{ApiError, ApiResult, ApiZone, CloudflareClient, UpdateRecord};
+pub use provider::{DnsProvider, Providers, Record};
+
+use serde::{Deserialize, Serialize};
+
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+/// The types of DNS record this crate manages.
+pub enum RecordType {
+    A,
+    #[serde(rename = "AAAA")]
+    Aaaa,
+}
+
+impl RecordType {
+    /// The address family the records hold.
+    pub fn family(self) -> &'static str {
+        match self {
+            RecordType::A => "IPv4",
+            RecordType::Aaaa => "IPv6",
+        }
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "A" => Ok(RecordType::A),
+            "AAAA" => Ok(RecordType::Aaaa),
+            _ => Err(format!("{} records are not supported", s)),
+        }
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordType::A => write!(f, "A"),
+            RecordType::Aaaa => write!(f, "AAAA"),
+        }
+    }
+}
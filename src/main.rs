@@ -1,172 +1,198 @@
-use serde::{Deserialize, Serialize};
-use serde_yaml::Value;
+mod cli;
+
+use clap::Parser;
+use cli::{Args, Command, ConfigCommand, USAGE_ERROR};
+use cloudflare_ddns::{
+    config::Config,
+    error::Error,
+    info,
+    ip::Detector,
+    log,
+    state::{self, State},
+    update::{self, Options},
+    verify, warn, Providers,
+};
+
+use std::{path::Path, process::ExitCode, time::Duration};
+
+const DEFAULT_INTERVAL: u64 = 300;
+const BACKOFF_BASE: u64 = 10;
+const BACKOFF_MAX: u64 = 1800;
 
-use std::{collections::HashMap, error::Error};
-
-const API_BASE: &str = "https://api.cloudflare.com/client/v4";
-const CONFIG_FILE: &str = "./config.yml";
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Config {
-    api_token: String,
-    zone_id: String,
-    subdomains: Vec<Subdomain>,
-    ttl: usize,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Subdomain {
-    name: String,
-    proxied: bool,
-    id: Option<String>,
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(e) => {
+            // --help and --version end up here too, as successes.
+            let _ = e.print();
+            return if e.use_stderr() {
+                ExitCode::from(USAGE_ERROR)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    log::set_level(args.verbosity());
+
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            e.exit_code()
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct UpdateRecord {
-    #[serde(rename(serialize = "type", deserialize = "type"))]
-    ty: String,
-    name: String,
-    content: String,
-    ttl: usize,
-    proxied: bool,
-}
+async fn run(args: Args) -> Result<(), Error> {
+    if let Some(Command::Config {
+        command: ConfigCommand::Convert { to },
+    }) = args.command
+    {
+        print!(
+            "{}",
+            Config::convert(args.config.as_deref(), args.format, to)?
+        );
+        return Ok(());
+    }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ApiMessage {
-    success: bool,
-    errors: Vec<HashMap<String, Value>>,
-    result: Option<OneOrMany>,
-}
+    let mut config = Config::load(args.config.as_deref(), args.format)?;
+    if args.daemon {
+        config.interval.get_or_insert(DEFAULT_INTERVAL);
+    }
+    let opts = Options::new(args.dry_run, args.force, args.ip.clone())?;
+
+    let providers = Providers::new(&config)?;
+    match args.command {
+        Some(Command::Verify) => return verify::run(&providers, &config).await,
+        Some(Command::Config {
+            command: ConfigCommand::Show { to },
+        }) => {
+            let text = to
+                .to_string(&config.redacted())
+                .map_err(|e| Error::Config(e.to_string()))?;
+            print!("{}", text);
+            return Ok(());
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Check,
+        }) => {
+            println!("Configuration is valid.");
+            return Ok(());
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Convert { .. },
+        })
+        | None => {}
+    }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ApiResult {
-    id: String,
-    #[serde(rename(serialize = "type", deserialize = "type"))]
-    ty: String,
-    name: String,
-    content: String,
-    proxied: bool,
-    ttl: usize,
-    zone_id: String,
-    zone_name: String,
-}
+    let detector = Detector::new(config.ip_sources.clone(), config.ip_consensus)?;
+
+    // A dry run must not leave anything behind, so it does not save the state.
+    let state_path = config
+        .state_file
+        .clone()
+        .or_else(state::default_path)
+        .filter(|_| !opts.dry_run);
+    let mut state = match &state_path {
+        Some(path) => State::load(path),
+        None => State::default(),
+    };
+    if args.refresh {
+        state.records.clear();
+    }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(untagged)]
-enum OneOrMany {
-    One(ApiResult),
-    Vec(Vec<ApiResult>),
+    if let Some(interval) = config.interval {
+        let interval = Duration::from_secs(interval);
+        run_daemon(
+            &providers,
+            &detector,
+            &config,
+            opts,
+            &mut state,
+            state_path.as_deref(),
+            interval,
+        )
+        .await
+    } else {
+        let result = update::check(&providers, &detector, &config, &opts, &mut state).await;
+        save_state(&mut state, state_path.as_deref());
+        result
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let f = std::fs::File::open(CONFIG_FILE)?;
-    let mut config: Config = serde_yaml::from_reader(f)?;
-    config.subdomains.iter_mut().for_each(|sd| {
-        if sd.name == "" {
-            sd.name = "@".to_string();
+fn save_state(state: &mut State, path: Option<&Path>) {
+    if let Some(path) = path {
+        if let Err(e) = state.save(path) {
+            warn!("Could not save state to {}: {}", path.display(), e);
         }
-    });
-
-    let ip = get_ip().await?;
-    println!("Current ip: {}", ip);
-
-    match_subdomain_ids(&mut config).await?;
-
-    update_dns(&ip, &config).await?;
-
-    Ok(())
+    }
 }
 
-async fn get_ip() -> Result<String, Box<dyn Error>> {
-    let resp = reqwest::get("https://1.1.1.1/cdn-cgi/trace")
-        .await?
-        .text()
-        .await?;
-
-    resp.split_ascii_whitespace()
-        .find_map(|s| match s.split_once("=") {
-            Some(("ip", x)) => Some(x.to_string()),
-            _ => None,
-        })
-        .ok_or_else(|| "No ip found.".into())
-}
+async fn run_daemon(
+    providers: &Providers,
+    detector: &Detector,
+    config: &Config,
+    mut opts: Options,
+    state: &mut State,
+    state_path: Option<&Path>,
+    interval: Duration,
+) -> Result<(), Error> {
+    let shutdown = shutdown();
+    tokio::pin!(shutdown);
+    let mut failures = 0;
+
+    info!("Checking every {}s", interval.as_secs());
+    loop {
+        let result = update::check(providers, detector, config, &opts, state).await;
+        save_state(state, state_path);
+        // Only the first check is forced, later ones react to changes.
+        opts.force = false;
+
+        let delay = match result {
+            Ok(()) => {
+                failures = 0;
+                interval
+            }
+            Err(e) => {
+                failures += 1;
+                let delay = backoff(failures);
+                eprintln!("Update failed: {}. Retrying in {}s", e, delay.as_secs());
+                delay
+            }
+        };
 
-async fn match_subdomain_ids(config: &mut Config) -> Result<(), Box<dyn Error>> {
-    let req = format!("{}/zones/{}/dns_records?type=A", API_BASE, config.zone_id);
-
-    let client = reqwest::Client::new();
-    let records = client
-        .get(req)
-        .bearer_auth(&config.api_token)
-        .send()
-        .await?
-        .json::<ApiMessage>()
-        .await?;
-
-    if let Some(OneOrMany::Vec(ref results)) = records.result {
-        config
-            .subdomains
-            .iter_mut()
-            .for_each(|sd| match sd.name.as_str() {
-                "@" => {
-                    sd.id = results.iter().find_map(|r| {
-                        if r.zone_name == r.name {
-                            Some(r.id.clone())
-                        } else {
-                            None
-                        }
-                    });
-                }
-                _ => {
-                    sd.id = results.iter().find_map(|r| {
-                        if r.name.starts_with(&sd.name) {
-                            Some(r.id.clone())
-                        } else {
-                            None
-                        }
-                    });
-                }
-            })
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            result = &mut shutdown => {
+                result?;
+                break;
+            }
+        }
     }
+    info!("Shutting down");
 
     Ok(())
 }
 
-async fn update_dns(ip: &str, config: &Config) -> Result<(), Box<dyn Error>> {
-    let client = reqwest::Client::new();
-
-    for sd in &config.subdomains {
-        println!("Setting ip of {} to {}", sd.name.as_str(), ip);
-
-        let req = format!(
-            "{}/zones/{}/dns_records/{}",
-            API_BASE,
-            config.zone_id,
-            sd.id.as_ref().unwrap()
-        );
+/// Waits until the process is asked to stop: Ctrl-C, or SIGTERM on Unix.
+async fn shutdown() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-        let map = UpdateRecord {
-            ty: "A".to_string(),
-            name: sd.name.clone(),
-            content: ip.to_string(),
-            ttl: 1,
-            proxied: sd.proxied,
-        };
-
-        let res = client
-            .put(req)
-            .bearer_auth(&config.api_token)
-            .json(&map)
-            .send()
-            .await?
-            .json::<ApiMessage>()
-            .await?;
-        if !res.errors.is_empty() {
-            res.errors.iter().for_each(|e| eprintln!("{:#?}", e));
-            panic!("Errors submitting update.")
+        let mut sigterm = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = sigterm.recv() => Ok(()),
         }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
 
-    Ok(())
+/// Delay before the next attempt after `failures` consecutive failed checks.
+fn backoff(failures: u32) -> Duration {
+    let secs = BACKOFF_BASE.saturating_mul(1 << (failures - 1).min(16));
+    Duration::from_secs(secs.min(BACKOFF_MAX))
 }
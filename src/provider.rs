@@ -0,0 +1,219 @@
+//! The DNS services that host the configured zones.
+//!
+//! [`update::check`](crate::update::check) only talks to zones through
+//! [`DnsProvider`], so another service can be supported by implementing it and
+//! adding a variant to [`ProviderConfig`].
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    config::{Auth, Config, ProviderConfig, Zone},
+    error::Error,
+    CloudflareClient, RecordType, UpdateRecord,
+};
+
+mod cloudflare;
+mod powerdns;
+mod rfc2136;
+
+pub use powerdns::PowerDns;
+pub use rfc2136::Rfc2136;
+
+/// What [`DnsProvider::auto_ttl`] gives by default.
+const DEFAULT_TTL: usize = 300;
+
+/// A record as published by a provider.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// How the provider refers to the record.
+    pub id: String,
+    /// Fully qualified name.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: RecordType,
+    pub content: String,
+    pub ttl: usize,
+    /// Whether Cloudflare proxies the traffic; never set by other providers.
+    #[serde(default)]
+    pub proxied: bool,
+}
+
+/// What the credentials may do with the records of a zone, as far as can be
+/// told without changing any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Edit,
+    ReadOnly,
+    Unknown,
+}
+
+/// A DNS service that hosts zones.
+///
+/// Zones are referred to by the id [`DnsProvider::zone_id`] returns for them,
+/// records by the relative names of the subdomains they belong to.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Checks that the credentials are accepted. Returns a description of them.
+    async fn verify(&self) -> Result<String, Error>;
+
+    /// How the provider refers to the zone called `name`, by default the name
+    /// itself.
+    async fn zone_id(&self, name: &str) -> Result<String, Error> {
+        Ok(name.trim_end_matches('.').to_string())
+    }
+
+    /// Checks that the records of the zone can be read, and whether they can
+    /// be edited.
+    async fn verify_zone(&self, zone_id: &str) -> Result<Access, Error>;
+
+    /// The `ty` records of the subdomains of `zone` by subdomain name, leaving
+    /// out those without one. Fails if a subdomain matches several records or
+    /// several subdomains match the same record.
+    async fn find_records(
+        &self,
+        zone: &Zone,
+        zone_id: &str,
+        ty: RecordType,
+    ) -> Result<HashMap<String, Record>, Error>;
+
+    async fn create_record(&self, zone_id: &str, record: &UpdateRecord) -> Result<Record, Error>;
+
+    /// Replaces the contents of `existing`.
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        existing: &Record,
+        record: &UpdateRecord,
+    ) -> Result<Record, Error>;
+
+    async fn delete_record(&self, zone_id: &str, existing: &Record) -> Result<(), Error>;
+
+    /// The TTL records get when `ttl` is configured, where 1 is automatic.
+    fn ttl(&self, ttl: usize) -> usize {
+        match self.auto_ttl() {
+            Some(auto) if ttl == 1 => auto,
+            _ => ttl,
+        }
+    }
+
+    /// The TTL that stands in for an automatic one, or `None` if the provider
+    /// picks TTLs itself. Plain DNS has no automatic TTLs.
+    fn auto_ttl(&self) -> Option<usize> {
+        Some(DEFAULT_TTL)
+    }
+
+    /// Advice for when the credentials are rejected.
+    fn credentials_hint(&self) -> Option<&str> {
+        None
+    }
+
+    /// Advice for when the credentials cannot edit the records of a zone.
+    fn access_hint(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// The providers of the configured zones.
+pub struct Providers {
+    /// By the name zones refer to them with, where `None` is the Cloudflare
+    /// account of the top level credentials.
+    providers: BTreeMap<Option<String>, Box<dyn DnsProvider>>,
+}
+
+impl Providers {
+    /// Sets up the providers the zones of `config` use.
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let mut providers = BTreeMap::new();
+        for zone in &config.zones {
+            if providers.contains_key(&zone.provider) {
+                continue;
+            }
+            let provider = match &zone.provider {
+                None => {
+                    let auth = config.auth().ok_or_else(|| {
+                        Error::Config(format!("zone {} needs api_token or auth", zone))
+                    })?;
+                    cloudflare(auth, &config.api_url)?
+                }
+                Some(name) => match config.providers.get(name) {
+                    Some(provider) => build(name, provider, config)?,
+                    None => {
+                        return Err(Error::Config(format!(
+                            "zone {} uses unknown provider {}",
+                            zone, name
+                        )))
+                    }
+                },
+            };
+            providers.insert(zone.provider.clone(), provider);
+        }
+        Ok(Providers { providers })
+    }
+
+    /// The provider that hosts `zone`, which must be one of the configured zones.
+    pub fn get(&self, zone: &Zone) -> &dyn DnsProvider {
+        self.providers
+            .get(&zone.provider)
+            .expect("providers are set up for every zone")
+            .as_ref()
+    }
+
+    /// Every provider in use, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (Option<&str>, &dyn DnsProvider)> {
+        self.providers
+            .iter()
+            .map(|(name, provider)| (name.as_deref(), provider.as_ref()))
+    }
+}
+
+fn build(
+    name: &str,
+    provider: &ProviderConfig,
+    config: &Config,
+) -> Result<Box<dyn DnsProvider>, Error> {
+    match provider {
+        ProviderConfig::Cloudflare { auth, api_url, .. } => {
+            // Config::load turns api_token into auth, others may not have.
+            let auth = auth.as_ref().ok_or_else(|| {
+                Error::Config(format!("provider {} needs api_token or auth", name))
+            })?;
+            cloudflare(auth, api_url.as_deref().unwrap_or(&config.api_url))
+        }
+        ProviderConfig::Rfc2136 {
+            server,
+            key_name,
+            key_secret,
+        } => {
+            let key = BASE64
+                .decode(key_secret.expose()?)
+                .map_err(|e| Error::Config(format!("key_secret is not base64: {}", e)))?;
+            Ok(Box::new(Rfc2136::new(server, key_name, key)))
+        }
+        ProviderConfig::PowerDns { api_url, api_key } => {
+            Ok(Box::new(PowerDns::new(api_url, api_key.expose()?)?))
+        }
+    }
+}
+
+fn cloudflare(auth: &Auth, api_url: &str) -> Result<Box<dyn DnsProvider>, Error> {
+    Ok(Box::new(
+        CloudflareClient::new(auth)?.with_base_url(api_url),
+    ))
+}
+
+/// Fully qualified, lower-case form of `name` within `zone`, where `@` is the apex.
+pub fn fqdn(name: &str, zone: &str) -> String {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    if name == "@" || name.is_empty() || name == zone {
+        zone
+    } else if name.ends_with(&format!(".{}", zone)) {
+        name
+    } else {
+        format!("{}.{}", name, zone)
+    }
+}
@@ -0,0 +1,178 @@
+use async_trait::async_trait;
+
+use std::collections::HashMap;
+
+use super::{fqdn, Access, DnsProvider, Record};
+use crate::{
+    config::Zone, error::Error, ApiError, ApiResult, CloudflareClient, RecordType, UpdateRecord,
+};
+
+const DNS_EDIT: &str = "#dns_records:edit";
+
+impl TryFrom<ApiResult> for Record {
+    type Error = Error;
+
+    /// Fails for records of types this crate does not manage.
+    fn try_from(r: ApiResult) -> Result<Self, Error> {
+        let ty = r.ty.parse().map_err(|message| {
+            Error::Api(vec![ApiError {
+                code: 0,
+                message: format!("record {}: {}", r.name, message),
+            }])
+        })?;
+        Ok(Record {
+            id: r.id,
+            name: r.name,
+            ty,
+            content: r.content,
+            ttl: r.ttl,
+            proxied: r.proxied,
+        })
+    }
+}
+
+#[async_trait]
+impl DnsProvider for CloudflareClient {
+    /// Checks that the credentials are known to Cloudflare and, for tokens,
+    /// that the token is active.
+    async fn verify(&self) -> Result<String, Error> {
+        if !self.uses_token() {
+            let user = self.user().await?;
+            return Ok(format!("Global API key of {}: valid", user.email));
+        }
+        let token = self.verify_token().await?;
+        if token.status != "active" {
+            return Err(Error::Auth(vec![ApiError {
+                code: 0,
+                message: format!("token {} is {}", token.id, token.status),
+            }]));
+        }
+        Ok(match &token.expires_on {
+            Some(expires) => format!("Token {}: active, expires {}", token.id, expires),
+            None => format!("Token {}: active, does not expire", token.id),
+        })
+    }
+
+    async fn zone_id(&self, name: &str) -> Result<String, Error> {
+        let zones = self.list_zones(Some(name)).await?;
+        let found = zones
+            .into_iter()
+            .find(|z| z.name.eq_ignore_ascii_case(name.trim_end_matches('.')))
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "zone {} not found; check the name and that the token can access it",
+                    name
+                ))
+            })?;
+        Ok(found.id)
+    }
+
+    async fn verify_zone(&self, zone_id: &str) -> Result<Access, Error> {
+        let permissions = self.zone(zone_id).await?.permissions;
+        self.count_records(zone_id).await?;
+
+        // Editing cannot be tried without changing a record, so rely on the
+        // permissions Cloudflare reports for the zone.
+        Ok(if permissions.is_empty() {
+            Access::Unknown
+        } else if permissions.iter().any(|p| p == DNS_EDIT) {
+            Access::Edit
+        } else {
+            Access::ReadOnly
+        })
+    }
+
+    async fn find_records(
+        &self,
+        zone: &Zone,
+        zone_id: &str,
+        ty: RecordType,
+    ) -> Result<HashMap<String, Record>, Error> {
+        let results = self.list_records(zone_id, Some(ty)).await?;
+        debug!("Zone {} has {} {} records", zone_id, results.len(), ty);
+        // Records do not reliably name their zone, so it is looked up by id.
+        let zone_name = match &zone.name {
+            Some(name) => name.clone(),
+            None => self.zone(zone_id).await?.name,
+        };
+
+        let mut found = HashMap::new();
+        let mut errors = Vec::new();
+        let mut bound: HashMap<&str, &str> = HashMap::new();
+        for sd in zone.subdomains.iter().filter(|sd| sd.types.contains(&ty)) {
+            let matches: Vec<_> = results
+                .iter()
+                .filter(|r| r.name.eq_ignore_ascii_case(&fqdn(&sd.name, &zone_name)))
+                .collect();
+
+            match matches.as_slice() {
+                [] => {}
+                [r] => {
+                    if let Some(other) = bound.insert(&r.id, &sd.name) {
+                        errors.push(format!(
+                            "{} and {} both refer to {} record {}",
+                            other, sd.name, ty, r.name
+                        ));
+                    }
+                    found.insert(sd.name.clone(), Record::try_from((*r).clone())?);
+                }
+                _ => errors.push(format!(
+                    "{} {} records named {}",
+                    matches.len(),
+                    ty,
+                    matches[0].name
+                )),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::Config(format!(
+                "ambiguous records: {}",
+                errors.join("; ")
+            )));
+        }
+
+        Ok(found)
+    }
+
+    async fn create_record(&self, zone_id: &str, record: &UpdateRecord) -> Result<Record, Error> {
+        CloudflareClient::create_record(self, zone_id, record)
+            .await
+            .and_then(Record::try_from)
+    }
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        existing: &Record,
+        record: &UpdateRecord,
+    ) -> Result<Record, Error> {
+        CloudflareClient::update_record(self, zone_id, &existing.id, record)
+            .await
+            .and_then(Record::try_from)
+    }
+
+    async fn delete_record(&self, zone_id: &str, existing: &Record) -> Result<(), Error> {
+        CloudflareClient::delete_record(self, zone_id, &existing.id).await
+    }
+
+    /// Cloudflare has automatic TTLs.
+    fn auto_ttl(&self) -> Option<usize> {
+        None
+    }
+
+    fn credentials_hint(&self) -> Option<&str> {
+        Some(if self.uses_token() {
+            "Create a token under My Profile > API Tokens and set it as api_token."
+        } else {
+            "Check the email and the Global API Key under My Profile > API Tokens."
+        })
+    }
+
+    fn access_hint(&self) -> Option<&str> {
+        Some(
+            "Grant the credentials the Zone / DNS / Edit permission for the zones above \
+             (under Zone Resources).",
+        )
+    }
+}
@@ -0,0 +1,274 @@
+//! The HTTP API of PowerDNS Authoritative, which edits the records of a name
+//! and type as one record set (RRset).
+
+use async_trait::async_trait;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use std::{collections::HashMap, time::Duration};
+
+use super::{fqdn, Access, DnsProvider, Record};
+use crate::{config::Zone, error::Error, ApiError, RecordType, UpdateRecord};
+
+const REQUEST_TIMEOUT: u64 = 30;
+/// The only server a PowerDNS API serves.
+const SERVER_ID: &str = "localhost";
+
+/// A PowerDNS Authoritative server, authenticated with its API key.
+pub struct PowerDns {
+    http: reqwest::Client,
+    /// URL of the server, e.g. `http://127.0.0.1:8081/api/v1/servers/localhost`.
+    base: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiServer {
+    daemon_type: String,
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiZone {
+    #[serde(default)]
+    rrsets: Vec<RrSet>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RrSet {
+    /// Fully qualified, with the trailing dot.
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+    /// Absent when deleting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    /// `REPLACE` or `DELETE`, only in requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    changetype: Option<String>,
+    #[serde(default)]
+    records: Vec<RrRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RrRecord {
+    content: String,
+    #[serde(default)]
+    disabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Patch<'a> {
+    rrsets: &'a [RrSet],
+}
+
+impl PowerDns {
+    /// `api_url` is where the webserver of PowerDNS listens, with or without
+    /// the `/api/v1` path. The API key must have been resolved, as done by
+    /// `Config::load`.
+    pub fn new(api_url: &str, api_key: &str) -> Result<Self, Error> {
+        let mut key = HeaderValue::from_str(api_key)
+            .map_err(|_| Error::Config("api_key contains invalid characters".to_string()))?;
+        key.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert(HeaderName::from_static("x-api-key"), key);
+
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .build()?;
+        let api_url = api_url.trim_end_matches('/');
+        Ok(PowerDns {
+            http,
+            base: format!(
+                "{}/api/v1/servers/{}",
+                api_url.strip_suffix("/api/v1").unwrap_or(api_url),
+                SERVER_ID
+            ),
+        })
+    }
+
+    fn zone_url(&self, zone_id: &str) -> String {
+        format!("{}/zones/{}.", self.base, zone_id)
+    }
+
+    /// The zone with all its record sets.
+    async fn zone(&self, zone_id: &str) -> Result<ApiZone, Error> {
+        parse(&send(self.http.get(self.zone_url(zone_id))).await?)
+    }
+
+    /// Applies `rrset` to the zone in a single PATCH request.
+    async fn patch(&self, zone_id: &str, rrset: RrSet) -> Result<(), Error> {
+        let rrsets = [rrset];
+        let req = self
+            .http
+            .patch(self.zone_url(zone_id))
+            .json(&Patch { rrsets: &rrsets });
+        send(req).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for PowerDns {
+    async fn verify(&self) -> Result<String, Error> {
+        let server: ApiServer = parse(&send(self.http.get(&self.base)).await?)?;
+        Ok(format!(
+            "PowerDNS {} {} at {}: API key accepted",
+            server.daemon_type, server.version, self.base
+        ))
+    }
+
+    /// The API key gives access to every zone of the server.
+    async fn verify_zone(&self, zone_id: &str) -> Result<Access, Error> {
+        self.zone(zone_id).await?;
+        Ok(Access::Edit)
+    }
+
+    /// Reads the whole zone in one request and picks the record sets of the
+    /// subdomains from it. Records disabled in PowerDNS are not served, so
+    /// they do not count.
+    async fn find_records(
+        &self,
+        zone: &Zone,
+        zone_id: &str,
+        ty: RecordType,
+    ) -> Result<HashMap<String, Record>, Error> {
+        let rrsets = self.zone(zone_id).await?.rrsets;
+        let mut found = HashMap::new();
+        for sd in zone.subdomains.iter().filter(|sd| sd.types.contains(&ty)) {
+            let name = fqdn(&sd.name, zone_id);
+            let Some(rrset) = rrsets.iter().find(|r| {
+                r.ty == ty.to_string() && r.name.trim_end_matches('.').eq_ignore_ascii_case(&name)
+            }) else {
+                continue;
+            };
+            let content: Vec<_> = rrset
+                .records
+                .iter()
+                .filter(|r| !r.disabled)
+                .map(|r| r.content.as_str())
+                .collect();
+            if content.is_empty() {
+                continue;
+            }
+            found.insert(
+                sd.name.clone(),
+                Record {
+                    id: name.clone(),
+                    name,
+                    ty,
+                    content: content.join(", "),
+                    ttl: rrset.ttl.unwrap_or_default() as usize,
+                    proxied: false,
+                },
+            );
+        }
+        Ok(found)
+    }
+
+    async fn create_record(&self, zone_id: &str, record: &UpdateRecord) -> Result<Record, Error> {
+        let name = fqdn(&record.name, zone_id);
+        self.patch(
+            zone_id,
+            RrSet {
+                name: format!("{}.", name),
+                ty: record.ty.to_string(),
+                ttl: Some(record.ttl as u32),
+                changetype: Some("REPLACE".to_string()),
+                records: vec![RrRecord {
+                    content: record.content.clone(),
+                    disabled: false,
+                }],
+            },
+        )
+        .await?;
+        Ok(Record {
+            id: name.clone(),
+            name,
+            ty: record.ty,
+            content: record.content.clone(),
+            ttl: record.ttl,
+            proxied: false,
+        })
+    }
+
+    /// The same `REPLACE` as creating the record set, which also drops any
+    /// disabled records left in it.
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        _existing: &Record,
+        record: &UpdateRecord,
+    ) -> Result<Record, Error> {
+        self.create_record(zone_id, record).await
+    }
+
+    async fn delete_record(&self, zone_id: &str, existing: &Record) -> Result<(), Error> {
+        self.patch(
+            zone_id,
+            RrSet {
+                name: format!("{}.", existing.name.trim_end_matches('.')),
+                ty: existing.ty.to_string(),
+                ttl: None,
+                changetype: Some("DELETE".to_string()),
+                records: Vec::new(),
+            },
+        )
+        .await
+    }
+
+    fn credentials_hint(&self) -> Option<&str> {
+        Some(
+            "Check that api_key matches the api-key setting of PowerDNS and that \
+             webserver-allow-from admits this host.",
+        )
+    }
+
+    fn access_hint(&self) -> Option<&str> {
+        Some("Check that the zones above exist on the PowerDNS server.")
+    }
+}
+
+/// Sends an API request and returns the body of the response. Unsuccessful
+/// responses are errors with the message PowerDNS gives in `error`.
+async fn send(req: reqwest::RequestBuilder) -> Result<String, Error> {
+    let resp = req.send().await?;
+    let status = resp.status();
+    let body = resp.text().await?;
+    if status.is_success() {
+        return Ok(body);
+    }
+
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        error: String,
+    }
+    let message = serde_json::from_str::<ErrorBody>(&body).map_or_else(
+        |_| match body.trim() {
+            "" => status.to_string(),
+            body => body.to_string(),
+        },
+        |e| e.error,
+    );
+    let error = ApiError {
+        code: status.as_u16().into(),
+        message,
+    };
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        Err(Error::Auth(vec![error]))
+    } else {
+        Err(Error::Api(vec![error]))
+    }
+}
+
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|e| {
+        Error::Api(vec![ApiError {
+            code: 0,
+            message: format!("unexpected response from PowerDNS: {}", e),
+        }])
+    })
+}
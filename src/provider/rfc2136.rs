@@ -0,0 +1,653 @@
+//! Dynamic updates (RFC 2136) signed with TSIG (RFC 8945), sent over TCP to
+//! the primary server of the zones.
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{fqdn, Access, DnsProvider, Record};
+use crate::{config::Zone, error::Error, ApiError, RecordType, UpdateRecord};
+
+const REQUEST_TIMEOUT: u64 = 10;
+const DEFAULT_PORT: u16 = 53;
+/// Seconds the clocks of the host and the server may differ.
+const FUDGE: u16 = 300;
+const ALGORITHM: &str = "hmac-sha256";
+
+const OPCODE_QUERY: u16 = 0;
+const OPCODE_UPDATE: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+const RCODE_NXDOMAIN: u16 = 3;
+/// Response flag that the server is authoritative for the name.
+const FLAG_AA: u16 = 0x0400;
+
+/// A DNS server that accepts updates signed with an HMAC-SHA256 TSIG key.
+pub struct Rfc2136 {
+    /// `host:port` of the server.
+    server: String,
+    /// Lower-case name of the key without the trailing dot.
+    key_name: String,
+    key: Vec<u8>,
+}
+
+impl Rfc2136 {
+    /// `server` is a host name or address, optionally with a port.
+    pub fn new(server: &str, key_name: &str, key: Vec<u8>) -> Self {
+        let server = match (server.parse::<IpAddr>(), server.parse::<SocketAddr>()) {
+            (Ok(ip), _) => SocketAddr::new(ip, DEFAULT_PORT).to_string(),
+            (_, Ok(addr)) => addr.to_string(),
+            _ if server.contains(':') => server.to_string(),
+            _ => format!("{}:{}", server, DEFAULT_PORT),
+        };
+        Rfc2136 {
+            server,
+            key_name: key_name.trim_end_matches('.').to_ascii_lowercase(),
+            key,
+        }
+    }
+
+    /// Looks up the `ty` records called `name`, which are missing if the
+    /// server answers NXDOMAIN.
+    async fn query(&self, name: &str, ty: u16) -> Result<Response, Error> {
+        let mut msg = Message::new(OPCODE_QUERY);
+        msg.question(name, ty);
+        let resp = self.exchange(msg).await?;
+        match resp.rcode {
+            0 | RCODE_NXDOMAIN => Ok(resp),
+            rcode => Err(rcode_error(rcode, resp.tsig_error)),
+        }
+    }
+
+    /// Replaces the `ty` records called `name` in the zone with `content`, or
+    /// only deletes them without it.
+    async fn update(
+        &self,
+        zone: &str,
+        name: &str,
+        ty: RecordType,
+        content: Option<(&IpAddr, usize)>,
+    ) -> Result<(), Error> {
+        let mut msg = Message::new(OPCODE_UPDATE);
+        msg.question(zone, TYPE_SOA);
+        // Deletes the record set, see RFC 2136 section 2.5.2.
+        msg.update(name, type_code(ty), CLASS_ANY, 0, &[]);
+        if let Some((ip, ttl)) = content {
+            let rdata = match ip {
+                IpAddr::V4(ip) => ip.octets().to_vec(),
+                IpAddr::V6(ip) => ip.octets().to_vec(),
+            };
+            msg.update(name, type_code(ty), CLASS_IN, ttl as u32, &rdata);
+        }
+        let resp = self.exchange(msg).await?;
+        match resp.rcode {
+            0 => Ok(()),
+            rcode => Err(rcode_error(rcode, resp.tsig_error)),
+        }
+    }
+
+    /// Signs `msg`, sends it to the server and returns the response once its
+    /// signature is verified.
+    async fn exchange(&self, msg: Message) -> Result<Response, Error> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let id = msg.id;
+        let (request, request_mac) = self.sign(msg.finish(), time);
+
+        let bytes = self.send(&request).await.map_err(|e| {
+            Error::Network(Box::new(io::Error::new(
+                e.kind(),
+                format!("DNS server {}: {}", self.server, e),
+            )))
+        })?;
+        let resp = Response::parse(&bytes)
+            .ok_or_else(|| api_error(format!("malformed response from {}", self.server)))?;
+        if resp.id != id {
+            return Err(api_error(format!(
+                "response from {} does not match the request",
+                self.server
+            )));
+        }
+
+        // Servers cannot sign their answer if they do not accept the request's
+        // signature, and report why with the rcode.
+        if resp.rcode != 0 && resp.rcode != RCODE_NXDOMAIN {
+            return Ok(resp);
+        }
+        let Some(tsig) = &resp.tsig else {
+            return Err(auth_error(format!(
+                "response from {} is not signed",
+                self.server
+            )));
+        };
+        if !tsig.key_name.eq_ignore_ascii_case(&self.key_name) || tsig.mac.is_empty() {
+            return Err(auth_error(format!(
+                "response from {} is not signed with key {}",
+                self.server, self.key_name
+            )));
+        }
+        let mut unsigned = bytes[..tsig.start].to_vec();
+        unsigned[..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+        let arcount = u16::from_be_bytes([unsigned[10], unsigned[11]])
+            .checked_sub(1)
+            .ok_or_else(|| api_error(format!("malformed response from {}", self.server)))?;
+        unsigned[10..12].copy_from_slice(&arcount.to_be_bytes());
+        let mac = self.mac(
+            Some(&request_mac),
+            &unsigned,
+            tsig.time,
+            tsig.error,
+            &tsig.other,
+        );
+        if mac.verify_slice(&tsig.mac).is_err() {
+            return Err(auth_error(format!(
+                "response from {} has an invalid signature",
+                self.server
+            )));
+        }
+        if tsig.time.abs_diff(time) > u64::from(FUDGE) {
+            return Err(auth_error(format!(
+                "the clocks of this host and {} differ by more than {}s",
+                self.server, FUDGE
+            )));
+        }
+        Ok(resp)
+    }
+
+    async fn send(&self, request: &[u8]) -> io::Result<Vec<u8>> {
+        let exchange = async {
+            let mut stream = TcpStream::connect(&self.server).await?;
+            let len = u16::try_from(request.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "request too long"))?;
+            stream.write_all(&len.to_be_bytes()).await?;
+            stream.write_all(request).await?;
+            let len = stream.read_u16().await?;
+            let mut response = vec![0; len.into()];
+            stream.read_exact(&mut response).await?;
+            Ok(response)
+        };
+        tokio::time::timeout(Duration::from_secs(REQUEST_TIMEOUT), exchange)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no response"))?
+    }
+
+    /// Appends the TSIG record to `msg`. Returns the signed message and its MAC,
+    /// which the response is signed over.
+    fn sign(&self, mut msg: Vec<u8>, time: u64) -> (Vec<u8>, Vec<u8>) {
+        let mac = self
+            .mac(None, &msg, time, 0, &[])
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        let id = u16::from_be_bytes([msg[0], msg[1]]);
+
+        let mut rdata = Vec::new();
+        put_name(&mut rdata, ALGORITHM);
+        put_time(&mut rdata, time);
+        rdata.extend_from_slice(&FUDGE.to_be_bytes());
+        rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&mac);
+        rdata.extend_from_slice(&id.to_be_bytes());
+        // No error and no other data.
+        rdata.extend_from_slice(&[0; 4]);
+        put_record(&mut msg, &self.key_name, TYPE_TSIG, CLASS_ANY, 0, &rdata);
+
+        let arcount = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+        msg[10..12].copy_from_slice(&arcount.to_be_bytes());
+        (msg, mac)
+    }
+
+    /// HMAC of `msg` and the TSIG variables, preceded by the MAC of the request
+    /// when signing a response.
+    fn mac(
+        &self,
+        request_mac: Option<&[u8]>,
+        msg: &[u8],
+        time: u64,
+        error: u16,
+        other: &[u8],
+    ) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        if let Some(request_mac) = request_mac {
+            mac.update(&(request_mac.len() as u16).to_be_bytes());
+            mac.update(request_mac);
+        }
+        mac.update(msg);
+
+        let mut vars = Vec::new();
+        put_name(&mut vars, &self.key_name);
+        vars.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        vars.extend_from_slice(&0u32.to_be_bytes());
+        put_name(&mut vars, ALGORITHM);
+        put_time(&mut vars, time);
+        vars.extend_from_slice(&FUDGE.to_be_bytes());
+        vars.extend_from_slice(&error.to_be_bytes());
+        vars.extend_from_slice(&(other.len() as u16).to_be_bytes());
+        vars.extend_from_slice(other);
+        mac.update(&vars);
+        mac
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136 {
+    /// The key can only be checked with a request for a zone, see `verify_zone`.
+    async fn verify(&self) -> Result<String, Error> {
+        Ok(format!(
+            "TSIG key {} ({}) for {}",
+            self.key_name, ALGORITHM, self.server
+        ))
+    }
+
+    async fn verify_zone(&self, zone_id: &str) -> Result<Access, Error> {
+        let resp = self.query(zone_id, TYPE_SOA).await?;
+        let zone = fqdn("@", zone_id);
+        if resp.flags & FLAG_AA == 0
+            || !resp
+                .answers
+                .iter()
+                .any(|a| a.ty == TYPE_SOA && a.name.eq_ignore_ascii_case(&zone))
+        {
+            return Err(api_error(format!(
+                "{} is not authoritative for {}",
+                self.server, zone
+            )));
+        }
+        // Update policies cannot be read, only tried.
+        Ok(Access::Unknown)
+    }
+
+    /// Several addresses for a name are one record set, which updates replace
+    /// as a whole.
+    async fn find_records(
+        &self,
+        zone: &Zone,
+        zone_id: &str,
+        ty: RecordType,
+    ) -> Result<HashMap<String, Record>, Error> {
+        let mut found = HashMap::new();
+        for sd in zone.subdomains.iter().filter(|sd| sd.types.contains(&ty)) {
+            let name = fqdn(&sd.name, zone_id);
+            let resp = self.query(&name, type_code(ty)).await?;
+            let answers: Vec<_> = resp
+                .answers
+                .iter()
+                .filter(|a| a.ty == type_code(ty) && a.name.eq_ignore_ascii_case(&name))
+                .collect();
+            let Some(ttl) = answers.iter().map(|a| a.ttl).min() else {
+                continue;
+            };
+            let content = answers
+                .iter()
+                .filter_map(|a| address(&a.rdata))
+                .map(|ip| ip.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            found.insert(
+                sd.name.clone(),
+                Record {
+                    id: name.clone(),
+                    name,
+                    ty,
+                    content,
+                    ttl: ttl as usize,
+                    proxied: false,
+                },
+            );
+        }
+        Ok(found)
+    }
+
+    async fn create_record(&self, zone_id: &str, record: &UpdateRecord) -> Result<Record, Error> {
+        let name = fqdn(&record.name, zone_id);
+        let ip = record
+            .content
+            .parse()
+            .map_err(|_| api_error(format!("{} is not an address", record.content)))?;
+        self.update(zone_id, &name, record.ty, Some((&ip, record.ttl)))
+            .await?;
+        Ok(Record {
+            id: name.clone(),
+            name,
+            ty: record.ty,
+            content: record.content.clone(),
+            ttl: record.ttl,
+            proxied: false,
+        })
+    }
+
+    /// Replaces every record of the name and type, as there are no record ids.
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        _existing: &Record,
+        record: &UpdateRecord,
+    ) -> Result<Record, Error> {
+        self.create_record(zone_id, record).await
+    }
+
+    async fn delete_record(&self, zone_id: &str, existing: &Record) -> Result<(), Error> {
+        self.update(zone_id, &existing.name, existing.ty, None)
+            .await
+    }
+
+    fn credentials_hint(&self) -> Option<&str> {
+        Some("Check that key_name and key_secret match the HMAC-SHA256 key configured on the server.")
+    }
+
+    fn access_hint(&self) -> Option<&str> {
+        Some("Allow the key to update the zones above, e.g. with update-policy on the server.")
+    }
+}
+
+fn type_code(ty: RecordType) -> u16 {
+    match ty {
+        RecordType::A => 1,
+        RecordType::Aaaa => 28,
+    }
+}
+
+fn address(rdata: &[u8]) -> Option<IpAddr> {
+    match rdata.len() {
+        4 => <[u8; 4]>::try_from(rdata).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(rdata).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
+/// Error for a response with `rcode`, or the TSIG error the server reported.
+fn rcode_error(rcode: u16, tsig_error: u16) -> Error {
+    let (code, message) = match (tsig_error, rcode) {
+        (16, _) => (
+            16,
+            "BADSIG: the server rejected the signature; check key_secret",
+        ),
+        (17, _) => (
+            17,
+            "BADKEY: the server does not know the key; check key_name",
+        ),
+        (18, _) => (
+            18,
+            "BADTIME: the clocks of this host and the server differ too much",
+        ),
+        (_, 1) => (1, "FORMERR: the server could not parse the request"),
+        (_, 2) => (2, "SERVFAIL: the server failed to process the request"),
+        (_, 3) => (3, "NXDOMAIN: the name does not exist"),
+        (_, 4) => (4, "NOTIMP: the server does not support the request"),
+        (_, 5) => (
+            5,
+            "REFUSED: the server does not allow the key to update the zone",
+        ),
+        (_, 6) => (6, "YXDOMAIN: a name exists that should not"),
+        (_, 7) => (7, "YXRRSET: a record set exists that should not"),
+        (_, 8) => (8, "NXRRSET: a record set does not exist that should"),
+        (_, 9) => (
+            9,
+            "NOTAUTH: the server is not authoritative for the zone or rejected the key",
+        ),
+        (_, 10) => (10, "NOTZONE: the name is not within the zone"),
+        (_, rcode) => (rcode, "the server returned an unknown error"),
+    };
+    let errors = vec![ApiError {
+        code: code.into(),
+        message: message.to_string(),
+    }];
+    if matches!(code, 5 | 9 | 16..=18) {
+        Error::Auth(errors)
+    } else {
+        Error::Api(errors)
+    }
+}
+
+fn api_error(message: String) -> Error {
+    Error::Api(vec![ApiError { code: 0, message }])
+}
+
+fn auth_error(message: String) -> Error {
+    Error::Auth(vec![ApiError { code: 0, message }])
+}
+
+/// A DNS message being built, with names written without compression.
+struct Message {
+    id: u16,
+    opcode: u16,
+    /// Entries in the four sections.
+    counts: [u16; 4],
+    body: Vec<u8>,
+}
+
+impl Message {
+    fn new(opcode: u16) -> Self {
+        // The ids only have to tell responses apart on a single connection.
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos() as u16);
+        Message {
+            id,
+            opcode,
+            counts: [0; 4],
+            body: Vec::new(),
+        }
+    }
+
+    /// Adds a question, or the zone of an update.
+    fn question(&mut self, name: &str, ty: u16) {
+        put_name(&mut self.body, name);
+        self.body.extend_from_slice(&ty.to_be_bytes());
+        self.body.extend_from_slice(&CLASS_IN.to_be_bytes());
+        self.counts[0] += 1;
+    }
+
+    /// Adds a record to the update section, which must follow the zone.
+    fn update(&mut self, name: &str, ty: u16, class: u16, ttl: u32, rdata: &[u8]) {
+        put_record(&mut self.body, name, ty, class, ttl, rdata);
+        self.counts[2] += 1;
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut msg = Vec::with_capacity(12 + self.body.len());
+        msg.extend_from_slice(&self.id.to_be_bytes());
+        msg.extend_from_slice(&(self.opcode << 11).to_be_bytes());
+        for count in self.counts {
+            msg.extend_from_slice(&count.to_be_bytes());
+        }
+        msg.extend_from_slice(&self.body);
+        msg
+    }
+}
+
+fn put_name(buf: &mut Vec<u8>, name: &str) {
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        buf.push(label.len() as u8);
+        buf.extend(label.bytes().map(|b| b.to_ascii_lowercase()));
+    }
+    buf.push(0);
+}
+
+fn put_record(buf: &mut Vec<u8>, name: &str, ty: u16, class: u16, ttl: u32, rdata: &[u8]) {
+    put_name(buf, name);
+    buf.extend_from_slice(&ty.to_be_bytes());
+    buf.extend_from_slice(&class.to_be_bytes());
+    buf.extend_from_slice(&ttl.to_be_bytes());
+    buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    buf.extend_from_slice(rdata);
+}
+
+/// Seconds since the epoch as the 48 bit number TSIG uses.
+fn put_time(buf: &mut Vec<u8>, time: u64) {
+    buf.extend_from_slice(&time.to_be_bytes()[2..]);
+}
+
+/// The parts of a response that are needed.
+struct Response {
+    id: u16,
+    flags: u16,
+    rcode: u16,
+    answers: Vec<Answer>,
+    tsig: Option<Tsig>,
+    /// Error reported in the TSIG record, if any.
+    tsig_error: u16,
+}
+
+struct Answer {
+    name: String,
+    ty: u16,
+    ttl: u32,
+    rdata: Vec<u8>,
+}
+
+struct Tsig {
+    /// Offset of the record in the response.
+    start: usize,
+    key_name: String,
+    time: u64,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl Response {
+    fn parse(msg: &[u8]) -> Option<Self> {
+        let mut r = Reader { msg, pos: 0 };
+        let id = r.u16()?;
+        let flags = r.u16()?;
+        let counts = [r.u16()?, r.u16()?, r.u16()?, r.u16()?];
+        for _ in 0..counts[0] {
+            r.name()?;
+            r.bytes(4)?;
+        }
+
+        let mut answers = Vec::new();
+        let mut tsig = None;
+        let records = counts[1] as usize + counts[2] as usize + counts[3] as usize;
+        for i in 0..records {
+            let start = r.pos;
+            let name = r.name()?;
+            let ty = r.u16()?;
+            r.u16()?;
+            let ttl = r.u32()?;
+            let len = r.u16()?.into();
+            let rdata_start = r.pos;
+            // RFC 8945 section 5.1: only the last record of the additional section.
+            if ty == TYPE_TSIG && i == records - 1 && counts[3] > 0 {
+                let key_name = name;
+                r.name()?;
+                let time = (u64::from(r.u16()?) << 32) | u64::from(r.u32()?);
+                r.u16()?;
+                let mac_len = r.u16()?.into();
+                let mac = r.bytes(mac_len)?.to_vec();
+                let original_id = r.u16()?;
+                let error = r.u16()?;
+                let other_len = r.u16()?.into();
+                let other = r.bytes(other_len)?.to_vec();
+                tsig = Some(Tsig {
+                    start,
+                    key_name,
+                    time,
+                    mac,
+                    original_id,
+                    error,
+                    other,
+                });
+            } else {
+                let rdata = r.bytes(len)?.to_vec();
+                if i < counts[1] as usize {
+                    answers.push(Answer {
+                        name,
+                        ty,
+                        ttl,
+                        rdata,
+                    });
+                }
+            }
+            if r.pos != rdata_start + len {
+                return None;
+            }
+        }
+
+        Some(Response {
+            id,
+            flags,
+            rcode: flags & 0xf,
+            answers,
+            tsig_error: tsig.as_ref().map_or(0, |t| t.error),
+            tsig,
+        })
+    }
+}
+
+struct Reader<'a> {
+    msg: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.msg.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Reads a name, following compression pointers, without the trailing dot.
+    fn name(&mut self) -> Option<String> {
+        let mut labels = Vec::new();
+        let mut pos = self.pos;
+        let mut end = None;
+        // Each pointer must lead further back, so there cannot be more jumps
+        // than bytes before them.
+        for _ in 0..self.msg.len() {
+            let len = *self.msg.get(pos)?;
+            match len {
+                0 => {
+                    self.pos = end.unwrap_or(pos + 1);
+                    return Some(labels.join("."));
+                }
+                1..=63 => {
+                    let label = self.msg.get(pos + 1..pos + 1 + len as usize)?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos += 1 + len as usize;
+                }
+                0xc0.. => {
+                    let target =
+                        usize::from(u16::from_be_bytes([len, *self.msg.get(pos + 1)?]) & 0x3fff);
+                    if target >= pos {
+                        return None;
+                    }
+                    end.get_or_insert(pos + 2);
+                    pos = target;
+                }
+                _ => return None,
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::{fmt, process::Command};
+
+use crate::error::Error;
+
+/// A credential from the config. It is either given literally or read from
+/// `env:NAME`, `file:PATH` or the output of `cmd:COMMAND`. Literal values are
+/// never shown in `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret {
+    /// As written in the config.
+    source: String,
+    /// The resolved value.
+    value: Option<String>,
+}
+
+impl Secret {
+    /// The value. Literal values are known from the start, those the config
+    /// refers to once resolved, as done by `Config::load`.
+    pub fn expose(&self) -> Result<&str, Error> {
+        self.value
+            .as_deref()
+            .ok_or_else(|| Error::Config(format!("{} has not been resolved", self)))
+    }
+
+    /// Whether the config refers to the value instead of containing it.
+    pub fn is_reference(&self) -> bool {
+        ["env:", "file:", "cmd:"]
+            .iter()
+            .any(|prefix| self.source.starts_with(prefix))
+    }
+
+    /// A copy that shows and serializes as `self` is displayed, without the value.
+    pub fn redacted(&self) -> Secret {
+        Secret {
+            source: self.to_string(),
+            value: None,
+        }
+    }
+
+    /// Looks up the value the config refers to and returns it.
+    pub fn resolve(&mut self) -> Result<&str, String> {
+        let value = if let Some(name) = self.source.strip_prefix("env:") {
+            std::env::var(name).map_err(|e| format!("environment variable {}: {}", name, e))?
+        } else if let Some(path) = self.source.strip_prefix("file:") {
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?
+        } else if let Some(cmd) = self.source.strip_prefix("cmd:") {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(cmd)
+                .output()
+                .map_err(|e| format!("{}: {}", cmd, e))?;
+            if !output.status.success() {
+                return Err(format!("{}: {}", cmd, output.status));
+            }
+            String::from_utf8(output.stdout).map_err(|_| format!("{}: output is not UTF-8", cmd))?
+        } else {
+            self.source.clone()
+        };
+
+        let value = value.trim().to_string();
+        if value.is_empty() {
+            return Err(format!("{} is empty", self));
+        }
+        Ok(self.value.insert(value))
+    }
+}
+
+impl From<String> for Secret {
+    fn from(source: String) -> Self {
+        let mut secret = Secret {
+            source,
+            value: None,
+        };
+        if !secret.is_reference() {
+            // Cannot fail for literal values other than empty ones, which
+            // stay unresolved.
+            let _ = secret.resolve();
+        }
+        secret
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", self)
+    }
+}
+
+/// Shows where the value comes from, or `<redacted>` for literal values.
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_reference() {
+            write!(f, "{}", self.source)
+        } else {
+            write!(f, "<redacted>")
+        }
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.source.serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d).map(Secret::from)
+    }
+}
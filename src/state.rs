@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::Config,
+    provider::{Providers, Record},
+    RecordType,
+};
+
+/// What is known about the zones and remote records between checks. Kept in
+/// memory by the daemon and in the state file between runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Unix time the state was last saved.
+    #[serde(default)]
+    pub updated_at: u64,
+    /// Zone ids by zone name.
+    #[serde(default)]
+    pub zone_ids: HashMap<String, String>,
+    /// The records as last seen or published.
+    #[serde(default, with = "record_list")]
+    pub records: HashMap<RecordKey, Record>,
+    /// Providers whose credentials were accepted during this process, by the
+    /// name zones refer to them with.
+    #[serde(skip)]
+    pub verified: HashSet<Option<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RecordKey {
+    pub zone_id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: RecordType,
+}
+
+impl RecordKey {
+    pub fn new(zone_id: &str, name: &str, ty: RecordType) -> Self {
+        RecordKey {
+            zone_id: zone_id.to_string(),
+            name: name.to_string(),
+            ty,
+        }
+    }
+}
+
+impl State {
+    /// Reads the state file, starting over if it is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return State::default(),
+            Err(e) => {
+                warn!("Ignoring state file {}: {}", path.display(), e);
+                return State::default();
+            }
+        };
+        serde_json::from_slice(&data).unwrap_or_else(|e| {
+            warn!("Ignoring state file {}: {}", path.display(), e);
+            State::default()
+        })
+    }
+
+    pub fn save(&mut self, path: &Path) -> std::io::Result<()> {
+        self.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first so an interrupted run cannot leave a
+        // truncated state behind.
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// Whether every configured `ty` record is known to hold `ip` with the
+    /// configured settings, so nothing needs to be sent.
+    pub fn is_current(
+        &self,
+        config: &Config,
+        providers: &Providers,
+        ty: RecordType,
+        ip: &IpAddr,
+    ) -> bool {
+        let content = ip.to_string();
+        config.zones.iter().all(|zone| {
+            let provider = providers.get(zone);
+            let zone_id = match (&zone.zone_id, &zone.name) {
+                (Some(id), _) => id,
+                (None, Some(name)) => match self.zone_ids.get(name) {
+                    Some(id) => id,
+                    None => return false,
+                },
+                (None, None) => return false,
+            };
+            zone.subdomains
+                .iter()
+                .filter(|sd| sd.types.contains(&ty))
+                .all(|sd| {
+                    self.records
+                        .get(&RecordKey::new(zone_id, &sd.name, ty))
+                        .is_some_and(|r| {
+                            r.content == content
+                                && r.proxied == sd.proxied(zone)
+                                && r.ttl == provider.ttl(sd.ttl(zone, config.ttl))
+                        })
+                })
+        })
+    }
+}
+
+/// Default location of the state file: `$XDG_STATE_HOME/cloudflare-ddns/state.json`,
+/// falling back to `~/.local/state`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(base.join("cloudflare-ddns").join("state.json"))
+}
+
+/// Stores the records as a list, as JSON objects cannot have structured keys.
+mod record_list {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use std::collections::HashMap;
+
+    use super::RecordKey;
+    use crate::provider::Record;
+
+    #[derive(Serialize, Deserialize)]
+    struct Entry<K, R> {
+        key: K,
+        record: R,
+    }
+
+    pub fn serialize<S: Serializer>(
+        records: &HashMap<RecordKey, Record>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        s.collect_seq(records.iter().map(|(key, record)| Entry { key, record }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<HashMap<RecordKey, Record>, D::Error> {
+        let entries: Vec<Entry<RecordKey, Record>> = Vec::deserialize(d)?;
+        Ok(entries.into_iter().map(|e| (e.key, e.record)).collect())
+    }
+}
@@ -0,0 +1,383 @@
+//! Brings the configured records up to date.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    net::IpAddr,
+};
+
+use crate::{
+    config::{Config, Zone},
+    error::Error,
+    ip::Detector,
+    provider::{DnsProvider, Providers, Record},
+    state::{RecordKey, State},
+    RecordType, UpdateRecord,
+};
+
+/// What happened to the records during a check.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub unchanged: usize,
+    pub updated: usize,
+    pub created: usize,
+    pub failures: Vec<Error>,
+}
+
+impl Summary {
+    pub fn succeeded(&self) -> usize {
+        self.unchanged + self.updated + self.created
+    }
+
+    pub fn total(&self) -> usize {
+        self.succeeded() + self.failures.len()
+    }
+
+    pub fn fail(&mut self, context: String, e: Error) {
+        eprintln!("{}: {}", context, e);
+        self.failures.push(e);
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} unchanged, {} updated, {} created, {} failed",
+            self.unchanged,
+            self.updated,
+            self.created,
+            self.failures.len()
+        )
+    }
+}
+
+/// Per-run behaviour, as selected on the command line.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Only print the planned changes.
+    pub dry_run: bool,
+    /// Update records even if they already hold the current address.
+    pub force: bool,
+    /// Addresses to use instead of detecting them, at most one per family.
+    pub ips: Vec<IpAddr>,
+}
+
+impl Options {
+    pub fn new(dry_run: bool, force: bool, ips: Vec<IpAddr>) -> Result<Self, Error> {
+        for (i, ip) in ips.iter().enumerate() {
+            if ips[..i].iter().any(|other| other.is_ipv4() == ip.is_ipv4()) {
+                return Err(Error::Config(format!(
+                    "--ip given more than once for {}",
+                    if ip.is_ipv4() { "IPv4" } else { "IPv6" }
+                )));
+            }
+        }
+        Ok(Options {
+            dry_run,
+            force,
+            ips,
+        })
+    }
+
+    /// The address given with `--ip` for the family of `ty`.
+    pub fn ip(&self, ty: RecordType) -> Option<IpAddr> {
+        self.ips
+            .iter()
+            .copied()
+            .find(|ip| ip.is_ipv4() == (ty == RecordType::A))
+    }
+}
+
+/// Detects the current addresses and updates the records of every family that
+/// is not known to be current already. Record ids are only looked up when missing.
+///
+/// The credentials of a provider are verified before its first update. A
+/// failing provider, zone or record does not stop the others, and the records
+/// of a family without a detected address count as failed. If nothing
+/// succeeded the first error is returned as is, otherwise the run counts as a
+/// partial failure.
+pub async fn check(
+    providers: &Providers,
+    detector: &Detector,
+    config: &Config,
+    opts: &Options,
+    state: &mut State,
+) -> Result<(), Error> {
+    let mut ips = BTreeMap::new();
+    let mut detection_errors = BTreeMap::new();
+    for ty in [RecordType::A, RecordType::Aaaa] {
+        if !config.manages(ty) {
+            continue;
+        }
+        let detected = if opts.ips.is_empty() {
+            detector.get_ip(ty).await
+        } else {
+            opts.ip(ty)
+                .ok_or_else(|| Error::IpDetection(format!("{}: not given with --ip", ty.family())))
+        };
+        match detected {
+            Ok(ip) => {
+                if state.is_current(config, providers, ty, &ip) {
+                    debug!("Current {} address: {}", ty.family(), ip);
+                } else {
+                    info!("Current {} address: {}", ty.family(), ip);
+                }
+                ips.insert(ty, ip);
+            }
+            Err(e) => {
+                warn!("{}", e);
+                detection_errors.insert(ty, e);
+            }
+        }
+    }
+    if ips.is_empty() {
+        return Err(detection_errors
+            .pop_last()
+            .map(|(_, e)| e)
+            .unwrap_or_else(|| Error::Config("no subdomains configured".to_string())));
+    }
+
+    let mut summary = Summary::default();
+    for sd in config.zones.iter().flat_map(|zone| &zone.subdomains) {
+        for ty in &sd.types {
+            if let Some(e) = detection_errors.get(ty) {
+                warn!(
+                    "Skipping {} record of {}: no {} address.",
+                    ty,
+                    sd.name,
+                    ty.family()
+                );
+                summary.failures.push(e.duplicate());
+            }
+        }
+    }
+    // Providers whose credentials could not be verified, which are not tried
+    // again for their other zones during this check.
+    let mut unverified: HashMap<Option<String>, Error> = HashMap::new();
+    for (ty, ip) in &ips {
+        if !opts.force && state.is_current(config, providers, *ty, ip) {
+            debug!("{} records are up to date", ty);
+            continue;
+        }
+
+        let failed = summary.failures.len();
+        for zone in &config.zones {
+            let provider = providers.get(zone);
+            let context = format!("Could not update {} records in zone {}", ty, zone);
+            if let Some(e) = unverified.get(&zone.provider) {
+                summary.fail(context, e.duplicate());
+                continue;
+            }
+            if !state.verified.contains(&zone.provider) {
+                if let Err(e) = provider.verify().await {
+                    summary.fail(context, e.duplicate());
+                    unverified.insert(zone.provider.clone(), e);
+                    continue;
+                }
+                state.verified.insert(zone.provider.clone());
+            }
+            if let Err(e) =
+                update_zone(provider, config, zone, *ty, ip, opts, state, &mut summary).await
+            {
+                summary.fail(context, e);
+            }
+        }
+
+        if summary.failures.len() > failed {
+            // The records may have been replaced, so look them up again next time.
+            state.records.retain(|key, _| key.ty != *ty);
+        }
+    }
+
+    if summary.total() > 0 {
+        if opts.dry_run {
+            info!("Records (dry run): {}", summary);
+        } else {
+            info!("Records: {}", summary);
+        }
+    }
+
+    if summary.failures.is_empty() {
+        Ok(())
+    } else if summary.succeeded() == 0 {
+        Err(summary.failures.swap_remove(0))
+    } else {
+        Err(Error::Partial {
+            failed: summary.failures.len(),
+            total: summary.total(),
+        })
+    }
+}
+
+/// Looks up the `ty` records of `zone` if needed and brings them up to date.
+#[allow(clippy::too_many_arguments)]
+pub async fn update_zone(
+    provider: &dyn DnsProvider,
+    config: &Config,
+    zone: &Zone,
+    ty: RecordType,
+    ip: &IpAddr,
+    opts: &Options,
+    state: &mut State,
+    summary: &mut Summary,
+) -> Result<(), Error> {
+    let zone_id = resolve_zone_id(provider, zone, &mut state.zone_ids).await?;
+    if zone.subdomains.iter().any(|sd| {
+        sd.types.contains(&ty)
+            && !state
+                .records
+                .contains_key(&RecordKey::new(&zone_id, &sd.name, ty))
+    }) {
+        match_subdomain_ids(provider, zone, &zone_id, ty, &mut state.records).await?;
+    }
+    update_dns(
+        provider,
+        config,
+        zone,
+        &zone_id,
+        ty,
+        ip,
+        opts,
+        &mut state.records,
+        summary,
+    )
+    .await
+}
+
+/// Id of `zone`, looked up by its name the first time it is needed.
+pub async fn resolve_zone_id(
+    provider: &dyn DnsProvider,
+    zone: &Zone,
+    zone_ids: &mut HashMap<String, String>,
+) -> Result<String, Error> {
+    let name = match (&zone.zone_id, &zone.name) {
+        (Some(id), _) => return Ok(id.clone()),
+        (None, Some(name)) => name,
+        (None, None) => return Err(Error::Config(format!("zone {} has no id", zone))),
+    };
+    if let Some(id) = zone_ids.get(name) {
+        return Ok(id.clone());
+    }
+
+    let id = provider.zone_id(name).await?;
+    debug!("Zone {} has id {}", name, id);
+    zone_ids.insert(name.clone(), id.clone());
+    Ok(id)
+}
+
+/// Finds the `ty` records of the subdomains of `zone` and stores them in
+/// `records`, forgetting those that do not exist. Fails if a subdomain matches
+/// several records or several subdomains match the same record.
+pub async fn match_subdomain_ids(
+    provider: &dyn DnsProvider,
+    zone: &Zone,
+    zone_id: &str,
+    ty: RecordType,
+    records: &mut HashMap<RecordKey, Record>,
+) -> Result<(), Error> {
+    let result = provider.find_records(zone, zone_id, ty).await;
+    let mut found = match result {
+        Ok(found) => found,
+        Err(e) => {
+            // Records that cannot be told apart must not be updated.
+            records.retain(|key, _| key.zone_id != zone_id || key.ty != ty);
+            return Err(e);
+        }
+    };
+    for sd in zone.subdomains.iter().filter(|sd| sd.types.contains(&ty)) {
+        let key = RecordKey::new(zone_id, &sd.name, ty);
+        match found.remove(&sd.name) {
+            Some(record) => records.insert(key, record),
+            None => records.remove(&key),
+        };
+    }
+    Ok(())
+}
+
+/// Creates or updates the `ty` records of all subdomains in `zone`. Failures are
+/// recorded in `summary`; only authentication errors abort the remaining records.
+#[allow(clippy::too_many_arguments)]
+pub async fn update_dns(
+    provider: &dyn DnsProvider,
+    config: &Config,
+    zone: &Zone,
+    zone_id: &str,
+    ty: RecordType,
+    ip: &IpAddr,
+    opts: &Options,
+    records: &mut HashMap<RecordKey, Record>,
+    summary: &mut Summary,
+) -> Result<(), Error> {
+    let content = ip.to_string();
+
+    for sd in zone.subdomains.iter().filter(|sd| sd.types.contains(&ty)) {
+        let key = RecordKey::new(zone_id, &sd.name, ty);
+        let map = UpdateRecord {
+            ty,
+            name: sd.name.clone(),
+            content: content.clone(),
+            ttl: provider.ttl(sd.ttl(zone, config.ttl)),
+            proxied: sd.proxied(zone),
+        };
+
+        let result = match records.get(&key) {
+            Some(r)
+                if !opts.force
+                    && r.content == map.content
+                    && r.proxied == map.proxied
+                    && r.ttl == map.ttl =>
+            {
+                debug!("{} record of {} is up to date", ty, sd.name);
+                summary.unchanged += 1;
+                continue;
+            }
+            Some(_) if opts.dry_run => {
+                info!("Would set {} record of {} to {}", ty, sd.name, ip);
+                summary.updated += 1;
+                continue;
+            }
+            Some(r) => {
+                info!("Setting {} record of {} to {}", ty, sd.name.as_str(), ip);
+                provider.update_record(zone_id, r, &map).await
+            }
+            None if !config.create_missing => {
+                summary.fail(
+                    format!("Skipping {} record of {}", ty, sd.name),
+                    Error::Config(
+                        "record does not exist and create_missing is disabled".to_string(),
+                    ),
+                );
+                continue;
+            }
+            None if opts.dry_run => {
+                info!("Would create {} record of {} with {}", ty, sd.name, ip);
+                summary.created += 1;
+                continue;
+            }
+            None => {
+                info!("Creating {} record of {} with {}", ty, sd.name.as_str(), ip);
+                provider.create_record(zone_id, &map).await
+            }
+        };
+
+        let record = match result {
+            Ok(record) => record,
+            Err(e @ Error::Auth(_)) => return Err(e),
+            Err(e) => {
+                summary.fail(format!("Failed to update {} record of {}", ty, sd.name), e);
+                continue;
+            }
+        };
+
+        if records.contains_key(&key) {
+            summary.updated += 1;
+        } else {
+            info!("Created {} record {} ({})", ty, record.name, record.id);
+            summary.created += 1;
+        }
+        records.insert(key, record);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    config::{Config, Zone},
+    error::Error,
+    provider::{Access, DnsProvider, Providers},
+    update::resolve_zone_id,
+    ApiError,
+};
+
+/// Prints whether the credentials of every provider are usable and have DNS
+/// access to every configured zone. A failing provider or zone does not stop
+/// the others; only the zones of providers that failed are skipped.
+pub async fn run(providers: &Providers, config: &Config) -> Result<(), Error> {
+    let mut failure: Option<Error> = None;
+    let mut failed = HashSet::new();
+    for (name, provider) in providers.iter() {
+        let label = name.map_or(String::new(), |name| format!("Provider {}: ", name));
+        let e = match provider.verify().await {
+            Ok(description) => {
+                println!("{}{}", label, description);
+                continue;
+            }
+            Err(e) => e,
+        };
+        if let Error::Auth(_) = e {
+            println!("{}Credentials: rejected", label);
+            if let Some(hint) = provider.credentials_hint() {
+                println!("  {}", hint);
+            }
+        } else {
+            println!("{}Credentials: could not be checked", label);
+            println!("  {}", e);
+        }
+        failed.insert(name.map(str::to_string));
+        add(&mut failure, e);
+    }
+
+    let mut hints = Vec::new();
+    let mut zone_ids = HashMap::new();
+    for zone in &config.zones {
+        if failed.contains(&zone.provider) {
+            println!("Zone {}: not checked, as its provider failed", zone);
+            continue;
+        }
+        let provider = providers.get(zone);
+        if let Err(e) = verify_zone(provider, zone, &mut zone_ids).await {
+            println!("  {}", e);
+            add(&mut failure, in_zone(zone, e));
+            if let Some(hint) = provider.access_hint().filter(|h| !hints.contains(h)) {
+                hints.push(hint);
+            }
+        }
+    }
+
+    match failure {
+        None => {
+            println!("All zones are accessible.");
+            Ok(())
+        }
+        Some(e) => {
+            for hint in hints {
+                println!("{}", hint);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Adds `e` to the failures so far.
+fn add(failure: &mut Option<Error>, e: Error) {
+    *failure = Some(match failure.take() {
+        Some(other) => worse(other, e),
+        None => e,
+    });
+}
+
+/// Names `zone` in the messages of `e`.
+fn in_zone(zone: &Zone, e: Error) -> Error {
+    let prefix = |errors: Vec<ApiError>| {
+        errors
+            .into_iter()
+            .map(|e| ApiError {
+                code: e.code,
+                message: format!("zone {}: {}", zone, e.message),
+            })
+            .collect()
+    };
+    match e {
+        Error::Auth(errors) => Error::Auth(prefix(errors)),
+        Error::Api(errors) => Error::Api(prefix(errors)),
+        e => e,
+    }
+}
+
+/// The error to exit with when zones failed with both `a` and `b`. Rejected
+/// credentials come first, as they are what is being verified, then failures
+/// that left the zone unchecked. Errors of the same kind from the API are
+/// reported together.
+fn worse(a: Error, b: Error) -> Error {
+    fn severity(e: &Error) -> u8 {
+        match e {
+            Error::Auth(_) => 3,
+            Error::Network(_) | Error::Io(_) => 2,
+            Error::Api(_) => 1,
+            _ => 0,
+        }
+    }
+    match (a, b) {
+        (Error::Auth(mut a), Error::Auth(b)) => {
+            a.extend(b);
+            Error::Auth(a)
+        }
+        (Error::Api(mut a), Error::Api(b)) => {
+            a.extend(b);
+            Error::Api(a)
+        }
+        (a, b) if severity(&b) > severity(&a) => b,
+        (a, _) => a,
+    }
+}
+
+async fn verify_zone(
+    provider: &dyn DnsProvider,
+    zone: &Zone,
+    zone_ids: &mut HashMap<String, String>,
+) -> Result<(), Error> {
+    let zone_id = match resolve_zone_id(provider, zone, zone_ids).await {
+        Ok(id) => id,
+        Err(e) => {
+            println!("Zone {}: not accessible", zone);
+            return Err(e);
+        }
+    };
+    // Providers that refer to zones by name need not repeat it.
+    let title = if zone.to_string().trim_end_matches('.') == zone_id {
+        zone.to_string()
+    } else {
+        format!("{} ({})", zone, zone_id)
+    };
+
+    let access = match provider.verify_zone(&zone_id).await {
+        Ok(access) => access,
+        Err(e) => {
+            println!("Zone {}: not accessible", title);
+            return Err(e);
+        }
+    };
+    println!("Zone {}:", title);
+    println!("  read DNS records: yes");
+    let edit = match access {
+        Access::Edit => "yes",
+        Access::ReadOnly => "no",
+        Access::Unknown => "unknown",
+    };
+    println!("  edit DNS records: {}", edit);
+
+    if access == Access::ReadOnly {
+        return Err(Error::Auth(vec![ApiError {
+            code: 0,
+            message: "the credentials can only read DNS records".to_string(),
+        }]));
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+//! Helpers shared by the integration tests. Not every test uses all of them.
+#![allow(dead_code)]
+
+use cloudflare_ddns::{
+    config::Config,
+    error::Error,
+    ip::Detector,
+    state::State,
+    update::{self, Options},
+    Providers,
+};
+
+use std::{io::Write, net::IpAddr};
+
+/// The IPv4 address the tests publish.
+pub const V4: &str = "198.51.100.4";
+/// The IPv6 address the tests publish.
+pub const V6: &str = "2001:db8::4";
+
+pub fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+/// Writes `text` to a file called `name` in a new directory.
+pub fn write(name: &str, text: &str) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let mut file = std::fs::File::create(dir.path().join(name)).unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+    dir
+}
+
+/// Loads `yaml` as a config file, along with the environment.
+pub fn load(yaml: &str) -> Result<Config, Error> {
+    let dir = write("config.yml", yaml);
+    Config::load(Some(&dir.path().join("config.yml")), None)
+}
+
+/// Options that give [`V4`] and [`V6`] instead of detecting addresses.
+pub fn opts() -> Options {
+    Options::new(false, false, vec![ip(V4), ip(V6)]).unwrap()
+}
+
+/// Runs a check of `config` with the address sources it configures.
+pub async fn check(config: &Config, opts: &Options, state: &mut State) -> Result<(), Error> {
+    update::check(
+        &Providers::new(config)?,
+        &Detector::new(config.ip_sources.clone(), config.ip_consensus)?,
+        config,
+        opts,
+        state,
+    )
+    .await
+}
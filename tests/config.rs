@@ -0,0 +1,244 @@
+//! Loads configuration files and checks what is reported about them.
+
+mod common;
+
+use cloudflare_ddns::{
+    config::{Config, Format},
+    error::Error,
+    RecordType,
+};
+use common::write;
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Held by every test, as the config is read along with the environment.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Sets environment variables until dropped, keeping other tests out meanwhile.
+struct Env {
+    vars: Vec<&'static str>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Env {
+    fn set(vars: &[(&'static str, &str)]) -> Env {
+        let lock = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, value) in vars {
+            std::env::set_var(name, value);
+        }
+        Env {
+            vars: vars.iter().map(|(name, _)| *name).collect(),
+            _lock: lock,
+        }
+    }
+}
+
+impl Drop for Env {
+    fn drop(&mut self) {
+        for name in &self.vars {
+            std::env::remove_var(name);
+        }
+    }
+}
+
+/// The problems reported for `text` in a file called `name`, one per line
+/// with the directory of the file left out.
+fn problems(name: &str, text: &str) -> Vec<String> {
+    let dir = write(name, text);
+    let path = dir.path().join(name);
+    match Config::load(Some(&path), None) {
+        Ok(_) => panic!("expected {} to be rejected", name),
+        Err(Error::Config(message)) => {
+            let prefix = format!("{}/", dir.path().display());
+            message
+                .lines()
+                .filter(|line| !line.ends_with(" problems"))
+                .map(|line| line.trim().replace(&prefix, ""))
+                .collect()
+        }
+        Err(e) => panic!("expected a config error, got {:?}", e),
+    }
+}
+
+#[test]
+fn unknown_fields_do_not_hide_other_problems() {
+    let _env = Env::set(&[]);
+    let found = problems(
+        "config.yml",
+        "api_token: abc\nzone: example.com\nproxid: true\nsubdomains:\n  \
+         - name: www\n    ttl: 5\n  - name: www\n  - name: \"bad name\"\n  \
+         - name: vpn.example.com\n",
+    );
+
+    assert_eq!(found.len(), 5, "{:#?}", found);
+    assert!(found[0].starts_with("config.yml:3:9: unknown field `proxid`"));
+    assert!(found[1].starts_with("config.yml:6:10: invalid ttl 5"));
+    assert!(found[2].starts_with("config.yml:7:11: www is listed more than once"));
+    assert!(found[3].starts_with("config.yml:8:11: invalid name \"bad name\""));
+    assert!(found[4].starts_with("config.yml:9:11: vpn.example.com includes the zone"));
+}
+
+#[test]
+fn convert_does_not_drop_unknown_fields() {
+    let _env = Env::set(&[]);
+    let dir = write("config.yml", "api_token: abc\nzone_id: abc\nproxid: true\n");
+
+    let result = Config::convert(Some(&dir.path().join("config.yml")), None, Format::Json);
+
+    assert!(
+        matches!(&result, Err(Error::Config(m)) if m.contains("unknown field `proxid`")),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn interval_has_a_minimum() {
+    let text = "api_token: abc\nzone_id: abc\nsubdomains: [{ name: www }]\n";
+    let env = Env::set(&[]);
+    assert_eq!(
+        problems("config.yml", &format!("{}interval: 0\n", text)),
+        ["config.yml:4:11: interval 0 is too short: must be at least 30 seconds"]
+    );
+    drop(env);
+
+    let _env = Env::set(&[("CF_DDNS_INTERVAL", "10")]);
+    assert_eq!(
+        problems("config.yml", text),
+        ["CF_DDNS_INTERVAL: interval 10 is too short: must be at least 30 seconds"]
+    );
+}
+
+#[test]
+fn invalid_env_vars_do_not_take_over_the_file() {
+    let _env = Env::set(&[("CF_DDNS_SUBDOMAINS", "www:proxied,@:bogus")]);
+
+    let found = problems(
+        "config.yml",
+        "api_token: abc\nzone_id: abc\nsubdomains:\n  - name: www\n    ttl: 5\n",
+    );
+
+    assert_eq!(found.len(), 2, "{:#?}", found);
+    assert!(found[0].starts_with("CF_DDNS_SUBDOMAINS: unknown option \"bogus\" for @"));
+    assert!(found[1].starts_with("config.yml:5:10: invalid ttl 5 for www"));
+}
+
+#[test]
+fn unknown_fields_are_located_in_every_format() {
+    let _env = Env::set(&[]);
+    let found = [
+        problems(
+            "config.yml",
+            "api_token: abc\nzone_id: abc\nsubdomains:\n  - name: www\n    proxid: true\n",
+        ),
+        problems(
+            "config.toml",
+            "api_token = \"abc\"\nzone_id = \"abc\"\n\n[[subdomains]]\nname = \"www\"\nproxid = true\n",
+        ),
+        problems(
+            "config.json",
+            "{\n  \"api_token\": \"abc\",\n  \"zone_id\": \"abc\",\n  \"subdomains\": [\n    \
+             { \"name\": \"www\", \"proxid\": true }\n  ]\n}\n",
+        ),
+    ];
+    assert_eq!(
+        found,
+        [
+            ["config.yml:5:13: unknown field `proxid`, expected one of `name`, `proxied`, `types`, `ttl`"],
+            ["config.toml:6:10: unknown field `proxid`, expected one of `name`, `proxied`, `types`, `ttl`"],
+            // JSON positions are those of the end of the value.
+            ["config.json:5:35: unknown field `proxid`, expected one of `name`, `proxied`, `types`, `ttl`"],
+        ]
+    );
+}
+
+#[test]
+fn convert_round_trips_between_formats() {
+    let _env = Env::set(&[]);
+    let yaml = "api_token: abc\n\
+                providers:\n  pdns:\n    type: powerdns\n    api_url: http://localhost:8081\n    \
+                api_key: key\n\
+                ttl: 120\n\
+                interval: 300\n\
+                zones:\n  - zone_id: abc\n    proxied: true\n    subdomains:\n      \
+                - name: \"@\"\n      - name: www\n        types: [AAAA]\n        ttl: 60\n  \
+                - zone: example.org\n    provider: pdns\n    subdomains:\n      - name: vpn\n";
+    let dir = write("config.yml", yaml);
+    let original = Config::load(Some(&dir.path().join("config.yml")), None).unwrap();
+
+    let mut path = dir.path().join("config.yml");
+    for to in [Format::Toml, Format::Json, Format::Yaml] {
+        let text = Config::convert(Some(&path), None, to).unwrap();
+        path = dir.path().join(format!("converted.{}", to).to_lowercase());
+        std::fs::write(&path, text).unwrap();
+
+        let converted = Config::load(Some(&path), None).unwrap();
+        assert_eq!(
+            serde_json::to_value(converted.redacted()).unwrap(),
+            serde_json::to_value(original.redacted()).unwrap(),
+            "{}",
+            to
+        );
+    }
+}
+
+#[test]
+fn env_vars_override_the_file() {
+    let _env = Env::set(&[
+        (
+            "CF_DDNS_SUBDOMAINS",
+            "www:proxied, @:A:AAAA ,vpn:dns-only:120:AAAA",
+        ),
+        ("CF_DDNS_TTL", "300"),
+    ]);
+    let dir = write(
+        "config.yml",
+        "api_token: abc\nzone_id: abc\nttl: 60\ninterval: 600\nsubdomains:\n  - name: mail\n",
+    );
+
+    let config = Config::load(Some(&dir.path().join("config.yml")), None).unwrap();
+
+    assert_eq!(config.ttl, 300);
+    assert_eq!(config.interval, Some(600));
+    let zone = &config.zones[0];
+    let subdomains: Vec<_> = zone
+        .subdomains
+        .iter()
+        .map(|sd| (sd.name.as_str(), sd.proxied, sd.types.clone(), sd.ttl))
+        .collect();
+    assert_eq!(
+        subdomains,
+        [
+            ("www", Some(true), vec![RecordType::A], None),
+            ("@", None, vec![RecordType::A, RecordType::Aaaa], None),
+            ("vpn", Some(false), vec![RecordType::Aaaa], Some(120)),
+        ]
+    );
+}
+
+#[test]
+fn problems_are_reported_at_the_env_vars_that_set_them() {
+    let _env = Env::set(&[("CF_DDNS_SUBDOMAINS", "www:5,www")]);
+
+    let found = problems(
+        "config.yml",
+        "api_token: abc\nzone_id: abc\nttl: 5\nsubdomains:\n  - name: mail\n",
+    );
+
+    assert_eq!(found.len(), 3, "{:#?}", found);
+    assert!(
+        found[0].starts_with("config.yml:3:6: invalid ttl 5"),
+        "{}",
+        found[0]
+    );
+    assert!(
+        found[1].starts_with("CF_DDNS_SUBDOMAINS: invalid ttl 5 for www"),
+        "{}",
+        found[1]
+    );
+    assert!(
+        found[2].starts_with("CF_DDNS_SUBDOMAINS: www is listed more than once"),
+        "{}",
+        found[2]
+    );
+}
@@ -0,0 +1,270 @@
+//! Runs the update flow against a local mock of the PowerDNS Authoritative API.
+
+mod common;
+
+use cloudflare_ddns::{
+    config::Config,
+    error::Error,
+    state::{RecordKey, State},
+    update, verify, Providers, RecordType,
+};
+use common::{check, load, opts, V4, V6};
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{body_json, header, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use std::collections::HashMap;
+
+const API_KEY: &str = "test-key";
+
+/// `example.org` with the apex and `www`, and `example.net` with `home`,
+/// both hosted by the mock.
+fn config(server: &MockServer) -> Config {
+    load(&format!(
+        "providers:\n  pdns:\n    type: powerdns\n    api_url: {}\n    api_key: {}\n\
+         ttl: 120\n\
+         zones:\n  - zone: example.org\n    provider: pdns\n    subdomains:\n      \
+         - name: \"@\"\n      - name: www\n        types: [A, AAAA]\n  \
+         - zone: example.net\n    provider: pdns\n    subdomains:\n      \
+         - name: home\n        ttl: 1\n",
+        server.uri(),
+        API_KEY
+    ))
+    .unwrap()
+}
+
+fn zone_path(zone: &str) -> String {
+    format!("/api/v1/servers/localhost/zones/{}.", zone)
+}
+
+fn rrset(name: &str, ty: &str, ttl: u32, contents: &[&str]) -> Value {
+    json!({
+        "name": name,
+        "type": ty,
+        "ttl": ttl,
+        "records": contents
+            .iter()
+            .map(|c| json!({ "content": c, "disabled": false }))
+            .collect::<Vec<_>>(),
+        "comments": [],
+    })
+}
+
+async fn mock_server(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/api/v1/servers/localhost"))
+        .and(header("X-API-Key", API_KEY))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "type": "Server",
+            "id": "localhost",
+            "daemon_type": "authoritative",
+            "version": "4.9.1",
+        })))
+        .mount(server)
+        .await;
+}
+
+async fn mock_zone(server: &MockServer, zone: &str, rrsets: Vec<Value>) {
+    Mock::given(method("GET"))
+        .and(path(zone_path(zone)))
+        .and(header("X-API-Key", API_KEY))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": format!("{}.", zone),
+            "name": format!("{}.", zone),
+            "kind": "Native",
+            "rrsets": rrsets,
+        })))
+        .mount(server)
+        .await;
+}
+
+/// Expects exactly one PATCH that replaces the record set.
+async fn expect_replace(server: &MockServer, zone: &str, name: &str, ty: &str, ttl: u32) {
+    Mock::given(method("PATCH"))
+        .and(path(zone_path(zone)))
+        .and(header("X-API-Key", API_KEY))
+        .and(body_json(json!({
+            "rrsets": [{
+                "name": name,
+                "type": ty,
+                "ttl": ttl,
+                "changetype": "REPLACE",
+                "records": [{
+                    "content": if ty == "A" { V4 } else { V6 },
+                    "disabled": false,
+                }],
+            }],
+        })))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn check_updates_record_sets_in_every_zone() {
+    let server = MockServer::start().await;
+    mock_server(&server).await;
+    mock_zone(
+        &server,
+        "example.org",
+        vec![
+            rrset("example.org.", "SOA", 3600, &["ns1.example.org. ..."]),
+            rrset("example.org.", "A", 120, &[V4]),
+            rrset("www.example.org.", "A", 120, &["192.0.2.1", "192.0.2.2"]),
+        ],
+    )
+    .await;
+    mock_zone(
+        &server,
+        "example.net",
+        vec![rrset("home.example.net.", "A", 60, &[V4])],
+    )
+    .await;
+    expect_replace(&server, "example.org", "www.example.org.", "A", 120).await;
+    expect_replace(&server, "example.org", "www.example.org.", "AAAA", 120).await;
+    // PowerDNS has no automatic TTL, so `ttl: 1` publishes the default.
+    expect_replace(&server, "example.net", "home.example.net.", "A", 300).await;
+    let config = config(&server);
+    let mut state = State::default();
+
+    check(&config, &opts(), &mut state).await.unwrap();
+
+    let key = RecordKey::new("example.net", "home", RecordType::A);
+    assert_eq!(state.records[&key].ttl, 300);
+    assert_eq!(state.records[&key].content, V4);
+
+    // The zones are not fetched again while the state knows the record sets.
+    let requests = server.received_requests().await.unwrap().len();
+    check(&config, &opts(), &mut state).await.unwrap();
+    assert_eq!(server.received_requests().await.unwrap().len(), requests);
+}
+
+#[tokio::test]
+async fn disabled_records_do_not_count() {
+    let server = MockServer::start().await;
+    mock_zone(
+        &server,
+        "example.org",
+        vec![json!({
+            "name": "WWW.example.org.",
+            "type": "A",
+            "ttl": 120,
+            "records": [
+                { "content": "192.0.2.1", "disabled": true },
+                { "content": "192.0.2.2", "disabled": false },
+            ],
+        })],
+    )
+    .await;
+    let config = config(&server);
+    let providers = Providers::new(&config).unwrap();
+    let zone = &config.zones[0];
+    let mut records = HashMap::new();
+
+    update::match_subdomain_ids(
+        providers.get(zone),
+        zone,
+        "example.org",
+        RecordType::A,
+        &mut records,
+    )
+    .await
+    .unwrap();
+
+    let www = &records[&RecordKey::new("example.org", "www", RecordType::A)];
+    assert_eq!(www.content, "192.0.2.2");
+    assert_eq!(www.name, "www.example.org");
+    assert_eq!(records.len(), 1);
+}
+
+#[tokio::test]
+async fn rejected_api_key_is_an_auth_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(401).set_body_string("Unauthorized"))
+        .mount(&server)
+        .await;
+    let config = config(&server);
+
+    let result = check(&config, &opts(), &mut State::default()).await;
+
+    match result {
+        Err(Error::Auth(errors)) => {
+            assert_eq!(errors[0].code, 401);
+            assert_eq!(errors[0].message, "Unauthorized");
+        }
+        other => panic!("expected an auth error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn verify_reports_the_server_and_missing_zones() {
+    let server = MockServer::start().await;
+    mock_server(&server).await;
+    mock_zone(&server, "example.org", Vec::new()).await;
+    Mock::given(method("GET"))
+        .and(path(zone_path("example.net")))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({ "error": "Not Found" })))
+        .mount(&server)
+        .await;
+    let config = config(&server);
+    let providers = Providers::new(&config).unwrap();
+    let provider = providers.get(&config.zones[0]);
+
+    assert!(provider.verify().await.unwrap().contains("4.9.1"));
+    provider.verify_zone("example.org").await.unwrap();
+    match provider.verify_zone("example.net").await {
+        Err(Error::Api(errors)) => {
+            assert_eq!(errors[0].code, 404);
+            assert_eq!(errors[0].message, "Not Found");
+        }
+        other => panic!("expected an api error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn missing_zones_are_not_reported_as_rejected_credentials() {
+    let server = MockServer::start().await;
+    mock_server(&server).await;
+    mock_zone(&server, "example.org", Vec::new()).await;
+    Mock::given(method("GET"))
+        .and(path(zone_path("example.net")))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({ "error": "Not Found" })))
+        .mount(&server)
+        .await;
+    let config = config(&server);
+
+    let result = verify::run(&Providers::new(&config).unwrap(), &config).await;
+
+    match result {
+        Err(Error::Api(errors)) => {
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].code, 404);
+            assert!(errors[0].message.starts_with("zone example.net: "));
+        }
+        other => panic!("expected an api error, got {:?}", other),
+    }
+}
+
+#[test]
+fn config_checks_powerdns_providers() {
+    let result = load(
+        "providers:\n  pdns:\n    type: powerdns\n    api_url: not a url\n    \
+         api_key: env:CF_DDNS_TEST_UNSET_KEY\n\
+         zones:\n  - zone_id: abc\n    provider: pdns\n    subdomains:\n      - name: www\n",
+    );
+
+    let Err(Error::Config(message)) = result else {
+        panic!("expected a config error, got {:?}", result);
+    };
+    for expected in [
+        "invalid api_url",
+        "CF_DDNS_TEST_UNSET_KEY",
+        "zone_id is only known to Cloudflare",
+    ] {
+        assert!(message.contains(expected), "{}", message);
+    }
+}
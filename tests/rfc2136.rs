@@ -0,0 +1,473 @@
+//! Runs the update flow against a local authoritative server that checks TSIG
+//! signatures and applies RFC 2136 updates, built on hickory-proto so that the
+//! wire format is checked by an independent implementation.
+
+mod common;
+
+use cloudflare_ddns::{
+    config::Config,
+    error::Error,
+    provider::Access,
+    state::{RecordKey, State},
+    update, Providers, RecordType,
+};
+use common::{check, load, opts, V4, V6};
+use hickory_proto::{
+    op::{Message, MessageType, OpCode, ResponseCode},
+    rr::{
+        dnssec::{
+            rdata::tsig::{make_tsig_record, TsigAlgorithm, TSIG},
+            tsig::TSigner,
+        },
+        rdata::{A, AAAA, SOA},
+        DNSClass, Name, RData, Record, RecordType as DnsType,
+    },
+    serialize::binary::{BinEncodable, BinEncoder},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const KEY_NAME: &str = "ddns-key.";
+/// `ddns-secret-for-tests` in base64.
+const KEY: &str = "ZGRucy1zZWNyZXQtZm9yLXRlc3Rz";
+const ZONE: &str = "example.org.";
+
+/// Tells the server to misbehave.
+#[derive(Clone, Copy, PartialEq)]
+enum Fault {
+    None,
+    /// Sign responses with another key.
+    ForgeResponses,
+    /// Put the signature in the answer section.
+    SignAnswers,
+}
+
+/// An authoritative server for `ZONE` that only accepts requests signed with
+/// `key`.
+struct Server {
+    addr: SocketAddr,
+    records: Arc<Mutex<Vec<Record>>>,
+    /// Number of requests with a valid signature.
+    accepted: Arc<Mutex<usize>>,
+}
+
+impl Server {
+    async fn start(key: &[u8], records: Vec<Record>, fault: Fault) -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = Server {
+            addr: listener.local_addr().unwrap(),
+            records: Arc::new(Mutex::new(records)),
+            accepted: Arc::new(Mutex::new(0)),
+        };
+        let signer = TSigner::new(
+            key.to_vec(),
+            TsigAlgorithm::HmacSha256,
+            Name::from_str(KEY_NAME).unwrap(),
+            300,
+        )
+        .unwrap();
+        let (records, accepted) = (server.records.clone(), server.accepted.clone());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let len = stream.read_u16().await.unwrap();
+                let mut request = vec![0; len.into()];
+                stream.read_exact(&mut request).await.unwrap();
+                let response = respond(&signer, &records, &accepted, &request, fault);
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        server
+    }
+
+    /// The addresses and TTLs of the records of type `ty` called `name`.
+    fn get(&self, name: &str, ty: DnsType) -> Vec<(IpAddr, u32)> {
+        let name = Name::from_str(name).unwrap();
+        let mut found: Vec<_> = self
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.name() == &name && r.record_type() == ty)
+            .filter_map(|r| match r.data()? {
+                RData::A(a) => Some((IpAddr::V4(a.0), r.ttl())),
+                RData::AAAA(aaaa) => Some((IpAddr::V6(aaaa.0), r.ttl())),
+                _ => None,
+            })
+            .collect();
+        found.sort();
+        found
+    }
+
+    fn accepted(&self) -> usize {
+        *self.accepted.lock().unwrap()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn respond(
+    signer: &TSigner,
+    records: &Mutex<Vec<Record>>,
+    accepted: &Mutex<usize>,
+    bytes: &[u8],
+    fault: Fault,
+) -> Vec<u8> {
+    let request = Message::from_vec(bytes).unwrap();
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(request.op_code())
+        .add_queries(request.queries().to_vec());
+
+    let key_name = Name::from_str(KEY_NAME).unwrap();
+    let request_mac = match signer.verify_message_byte(None, bytes, true) {
+        Ok((mac, range, _)) if range.contains(&now()) => mac,
+        _ => {
+            // RFC 8945 section 5.3.2: unsigned NOTAUTH with BADSIG.
+            response.set_response_code(ResponseCode::NotAuth);
+            let tsig = TSIG::new(
+                TsigAlgorithm::HmacSha256,
+                now(),
+                300,
+                Vec::new(),
+                request.id(),
+                16,
+                Vec::new(),
+            );
+            response.add_additional(make_tsig_record(key_name, tsig));
+            return response.to_vec().unwrap();
+        }
+    };
+    *accepted.lock().unwrap() += 1;
+
+    let zone = Name::from_str(ZONE).unwrap();
+    let question = &request.queries()[0];
+    let mut records = records.lock().unwrap();
+    match request.op_code() {
+        OpCode::Query => {
+            response.set_authoritative(true);
+            if question.name() == &zone && question.query_type() == DnsType::SOA {
+                let soa = SOA::new(
+                    Name::from_str("ns.example.org.").unwrap(),
+                    Name::from_str("admin.example.org.").unwrap(),
+                    1,
+                    3600,
+                    600,
+                    86400,
+                    300,
+                );
+                response.add_answer(Record::from_rdata(zone, 3600, RData::SOA(soa)));
+            } else if !zone.zone_of(question.name()) {
+                response.set_response_code(ResponseCode::Refused);
+            } else {
+                let answers: Vec<_> = records
+                    .iter()
+                    .filter(|r| r.name() == question.name())
+                    .cloned()
+                    .collect();
+                if answers.is_empty() {
+                    response.set_response_code(ResponseCode::NXDomain);
+                }
+                response.add_answers(
+                    answers
+                        .into_iter()
+                        .filter(|r| r.record_type() == question.query_type()),
+                );
+            }
+        }
+        OpCode::Update if question.name() != &zone => {
+            response.set_response_code(ResponseCode::NotZone);
+        }
+        OpCode::Update => {
+            for update in request.name_servers() {
+                if update.dns_class() == DNSClass::ANY {
+                    records.retain(|r| {
+                        r.name() != update.name() || r.record_type() != update.record_type()
+                    });
+                } else {
+                    records.push(update.clone());
+                }
+            }
+        }
+        _ => {
+            response.set_response_code(ResponseCode::NotImp);
+        }
+    }
+
+    // Signed over the MAC of the request, see RFC 8945 section 4.3.1.
+    let mut signed = response.to_vec().unwrap();
+    let tsig = TSIG::new(
+        TsigAlgorithm::HmacSha256,
+        now(),
+        300,
+        Vec::new(),
+        response.id(),
+        0,
+        Vec::new(),
+    );
+    let mut tbs = (request_mac.len() as u16).to_be_bytes().to_vec();
+    tbs.extend_from_slice(&request_mac);
+    tbs.extend_from_slice(&signed);
+    let mut variables = Vec::new();
+    tsig.emit_tsig_for_mac(&mut BinEncoder::new(&mut variables), &key_name)
+        .unwrap();
+    tbs.extend_from_slice(&variables);
+    let mac = if fault == Fault::ForgeResponses {
+        TSigner::new(
+            b"another key".to_vec(),
+            TsigAlgorithm::HmacSha256,
+            key_name.clone(),
+            300,
+        )
+        .unwrap()
+        .sign(&tbs)
+        .unwrap()
+    } else {
+        signer.sign(&tbs).unwrap()
+    };
+    let mut rr = Vec::new();
+    make_tsig_record(key_name, tsig.set_mac(mac))
+        .emit(&mut BinEncoder::new(&mut rr))
+        .unwrap();
+    signed.extend_from_slice(&rr);
+    // ARCOUNT, or ANCOUNT to misplace the signature.
+    let count = if fault == Fault::SignAnswers { 6 } else { 10 };
+    let n = u16::from_be_bytes([signed[count], signed[count + 1]]) + 1;
+    signed[count..count + 2].copy_from_slice(&n.to_be_bytes());
+    signed
+}
+
+fn key() -> Vec<u8> {
+    b"ddns-secret-for-tests".to_vec()
+}
+
+fn a(name: &str, ip: &str, ttl: u32) -> Record {
+    Record::from_rdata(
+        Name::from_str(name).unwrap(),
+        ttl,
+        RData::A(A::from_str(ip).unwrap()),
+    )
+}
+
+fn aaaa(name: &str, ip: &str, ttl: u32) -> Record {
+    Record::from_rdata(
+        Name::from_str(name).unwrap(),
+        ttl,
+        RData::AAAA(AAAA::from_str(ip).unwrap()),
+    )
+}
+
+/// A config for `ZONE` at `server` with the apex and `www`.
+fn config(server: SocketAddr, key: &str) -> Config {
+    load(&format!(
+        "providers:\n  ns:\n    type: rfc2136\n    server: {}\n    key_name: {}\n    \
+         key_secret: {}\n\
+         zones:\n  - zone: {}\n    provider: ns\n    subdomains:\n      \
+         - name: \"@\"\n      - name: www\n        types: [A, AAAA]\n        ttl: 120\n",
+        server, KEY_NAME, key, "example.org"
+    ))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn check_replaces_and_adds_records() {
+    let server = Server::start(
+        &key(),
+        vec![
+            a("www.example.org.", "192.0.2.1", 120),
+            a("www.example.org.", "192.0.2.2", 120),
+            a("mail.example.org.", "192.0.2.9", 3600),
+        ],
+        Fault::None,
+    )
+    .await;
+    let config = config(server.addr, KEY);
+    let mut state = State::default();
+
+    check(&config, &opts(), &mut state).await.unwrap();
+
+    let v4 = V4.parse().unwrap();
+    let v6 = V6.parse().unwrap();
+    // The apex has the global TTL, automatic, which DNS itself cannot express.
+    assert_eq!(server.get("example.org.", DnsType::A), [(v4, 300)]);
+    assert_eq!(server.get("www.example.org.", DnsType::A), [(v4, 120)]);
+    assert_eq!(server.get("www.example.org.", DnsType::AAAA), [(v6, 120)]);
+    assert_eq!(server.get("mail.example.org.", DnsType::A).len(), 1);
+    let key = RecordKey::new("example.org", "www", RecordType::A);
+    assert_eq!(state.records[&key].name, "www.example.org");
+
+    // The state stands in for queries to the server on the next check.
+    let accepted = server.accepted();
+    check(&config, &opts(), &mut state).await.unwrap();
+    assert_eq!(server.accepted(), accepted);
+}
+
+#[tokio::test]
+async fn check_leaves_current_records_alone() {
+    let server = Server::start(
+        &key(),
+        vec![
+            a("example.org.", V4, 300),
+            a("www.example.org.", V4, 120),
+            aaaa("www.example.org.", V6, 120),
+        ],
+        Fault::None,
+    )
+    .await;
+    let config = config(server.addr, KEY);
+
+    check(&config, &opts(), &mut State::default())
+        .await
+        .unwrap();
+
+    // One query per record and no updates.
+    assert_eq!(server.accepted(), 3);
+}
+
+#[tokio::test]
+async fn several_addresses_are_one_record() {
+    let server = Server::start(
+        &key(),
+        vec![
+            a("www.example.org.", "192.0.2.1", 120),
+            a("www.example.org.", "192.0.2.2", 60),
+        ],
+        Fault::None,
+    )
+    .await;
+    let config = config(server.addr, KEY);
+    let providers = Providers::new(&config).unwrap();
+    let zone = &config.zones[0];
+    let mut records = HashMap::new();
+
+    update::match_subdomain_ids(
+        providers.get(zone),
+        zone,
+        "example.org",
+        RecordType::A,
+        &mut records,
+    )
+    .await
+    .unwrap();
+
+    let www = &records[&RecordKey::new("example.org", "www", RecordType::A)];
+    assert_eq!(www.content, "192.0.2.1, 192.0.2.2");
+    assert_eq!(www.ttl, 60);
+    assert!(!records.contains_key(&RecordKey::new("example.org", "@", RecordType::A)));
+}
+
+#[tokio::test]
+async fn verify_zone_checks_the_server_is_authoritative() {
+    let server = Server::start(&key(), Vec::new(), Fault::None).await;
+    let config = config(server.addr, KEY);
+    let providers = Providers::new(&config).unwrap();
+    let provider = providers.get(&config.zones[0]);
+
+    assert_eq!(
+        provider.verify_zone("example.org").await.unwrap(),
+        Access::Unknown
+    );
+    assert!(matches!(
+        provider.verify_zone("example.net").await,
+        Err(Error::Auth(errors)) if errors[0].code == 5
+    ));
+}
+
+#[tokio::test]
+async fn rejected_key_is_an_auth_error() {
+    let server = Server::start(b"the server's key", Vec::new(), Fault::None).await;
+    let config = config(server.addr, KEY);
+
+    let result = check(&config, &opts(), &mut State::default()).await;
+
+    match result {
+        Err(Error::Auth(errors)) => assert!(errors[0].message.contains("BADSIG")),
+        other => panic!("expected an auth error, got {:?}", other),
+    }
+    assert_eq!(server.accepted(), 0);
+}
+
+#[tokio::test]
+async fn forged_responses_are_rejected() {
+    let server = Server::start(&key(), Vec::new(), Fault::ForgeResponses).await;
+    let config = config(server.addr, KEY);
+
+    let result = check(&config, &opts(), &mut State::default()).await;
+
+    match result {
+        Err(Error::Auth(errors)) => {
+            assert!(errors[0].message.contains("invalid signature"))
+        }
+        other => panic!("expected an auth error, got {:?}", other),
+    }
+    // Nothing is updated on the strength of an answer that cannot be trusted.
+    assert!(server.get("example.org.", DnsType::A).is_empty());
+}
+
+#[tokio::test]
+async fn signatures_outside_the_additional_section_are_rejected() {
+    let server = Server::start(&key(), Vec::new(), Fault::SignAnswers).await;
+    let config = config(server.addr, KEY);
+
+    let result = check(&config, &opts(), &mut State::default()).await;
+
+    match result {
+        Err(Error::Auth(errors)) => assert!(errors[0].message.contains("not signed")),
+        other => panic!("expected an auth error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn unreachable_server_is_reported() {
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = config(closed.local_addr().unwrap(), KEY);
+    drop(closed);
+
+    let result = check(&config, &opts(), &mut State::default()).await;
+
+    assert!(matches!(result, Err(Error::Network(_))), "{:?}", result);
+}
+
+#[test]
+fn config_rejects_what_only_cloudflare_supports() {
+    let result = load(
+        "providers:\n  ns:\n    type: rfc2136\n    server: \"\"\n    key_name: ddns-key\n    \
+         key_secret: not base64!\n\
+         zones:\n  - zone_id: abc\n    provider: ns\n    proxied: true\n    subdomains:\n      \
+         - name: www\n        proxied: true\n",
+    );
+
+    let Err(Error::Config(message)) = result else {
+        panic!("expected a config error, got {:?}", result);
+    };
+    for expected in [
+        "key_secret is not base64",
+        "server is empty",
+        "zone_id is only known to Cloudflare",
+        "records of zone abc cannot be proxied",
+        "www cannot be proxied",
+    ] {
+        assert!(message.contains(expected), "{}", message);
+    }
+}
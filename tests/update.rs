@@ -0,0 +1,860 @@
+//! Runs the update flow against a local mock of the Cloudflare API and of an
+//! address reporting service.
+
+mod common;
+
+use cloudflare_ddns::{
+    config::{Auth, Config},
+    error::Error,
+    ip::Detector,
+    state::{RecordKey, State},
+    update::{self, Options, Summary},
+    verify, ApiResult, CloudflareClient, Providers, Record, RecordType,
+};
+use common::{check, ip, load};
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{body_partial_json, header, method, path, query_param},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use std::collections::HashMap;
+
+const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+const TOKEN: &str = "test-token";
+
+/// Loads `yaml` with the credentials and API URL of the mock prepended.
+fn config(server: &MockServer, yaml: &str) -> Config {
+    load(&format!(
+        "api_token: {}\napi_url: {}\n{}",
+        TOKEN,
+        server.uri(),
+        yaml
+    ))
+    .unwrap()
+}
+
+/// A zone with the apex and `www`, both dns-only with a TTL of 300.
+fn zone_config(server: &MockServer, extra: &str) -> Config {
+    config(
+        server,
+        &format!(
+            "zone_id: {}\nttl: 300\nsubdomains:\n  - name: \"@\"\n  - name: www\n{}",
+            ZONE_ID, extra
+        ),
+    )
+}
+
+fn client(server: &MockServer, config: &Config) -> CloudflareClient {
+    CloudflareClient::new(config.auth().unwrap())
+        .unwrap()
+        .with_base_url(&server.uri())
+}
+
+fn record(id: &str, name: &str, content: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "A",
+        "name": name,
+        "content": content,
+        "proxied": false,
+        "ttl": 300,
+    })
+}
+
+/// `record` as stored in the state after it was returned by the API.
+fn known(record: &Value) -> Record {
+    serde_json::from_value::<ApiResult>(record.clone())
+        .unwrap()
+        .try_into()
+        .unwrap()
+}
+
+fn success(result: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "success": true,
+        "errors": [],
+        "messages": [],
+        "result": result,
+    }))
+}
+
+fn page(results: Vec<Value>, page: usize, total_pages: usize) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "success": true,
+        "errors": [],
+        "result": results,
+        "result_info": {
+            "page": page,
+            "per_page": 100,
+            "count": results.len(),
+            "total_count": results.len(),
+            "total_pages": total_pages,
+        },
+    }))
+}
+
+fn failure(status: u16, code: u32, message: &str) -> ResponseTemplate {
+    ResponseTemplate::new(status).set_body_json(json!({
+        "success": false,
+        "errors": [{ "code": code, "message": message }],
+        "result": null,
+    }))
+}
+
+fn records_path() -> String {
+    format!("/zones/{}/dns_records", ZONE_ID)
+}
+
+fn key(name: &str) -> RecordKey {
+    RecordKey::new(ZONE_ID, name, RecordType::A)
+}
+
+/// Serves the zone `zone_id` as `example.com`.
+async fn mock_zone(server: &MockServer, zone_id: &str) {
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{}", zone_id)))
+        .respond_with(success(json!({ "id": zone_id, "name": "example.com" })))
+        .mount(server)
+        .await;
+}
+
+async fn mock_list(server: &MockServer, records: Vec<Value>) {
+    Mock::given(method("GET"))
+        .and(path(records_path()))
+        .and(query_param("type", "A"))
+        .respond_with(page(records, 1, 1))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn client_takes_plain_credentials() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/user/tokens/verify"))
+        .and(header("authorization", "Bearer plain-token"))
+        .respond_with(success(json!({ "id": "t1", "status": "active" })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let client = CloudflareClient::with_token("plain-token").unwrap();
+    client
+        .with_base_url(&server.uri())
+        .verify_token()
+        .await
+        .unwrap();
+    let auth = Auth::Token {
+        token: "plain-token".to_string().into(),
+    };
+    let client = CloudflareClient::new(&auth).unwrap();
+    client
+        .with_base_url(&server.uri())
+        .verify_token()
+        .await
+        .unwrap();
+
+    // References are only read by Config::load.
+    let auth = Auth::Token {
+        token: "env:CF_DDNS_TEST_TOKEN".to_string().into(),
+    };
+    assert!(matches!(
+        CloudflareClient::new(&auth),
+        Err(Error::Config(_))
+    ));
+}
+
+#[tokio::test]
+async fn match_finds_records_by_name() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "  - name: new\n");
+    mock_zone(&server, ZONE_ID).await;
+    mock_list(
+        &server,
+        vec![
+            record("1", "example.com", "192.0.2.1"),
+            record("2", "WWW.example.com", "192.0.2.1"),
+            record("3", "www.other.example.com", "192.0.2.1"),
+        ],
+    )
+    .await;
+
+    let mut records = HashMap::new();
+    records.insert(key("new"), known(&record("9", "new.example.com", "")));
+    update::match_subdomain_ids(
+        &client(&server, &config),
+        &config.zones[0],
+        ZONE_ID,
+        RecordType::A,
+        &mut records,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(records[&key("@")].id, "1");
+    assert_eq!(records[&key("www")].id, "2");
+    assert!(!records.contains_key(&key("new")));
+}
+
+#[tokio::test]
+async fn match_follows_pagination() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "");
+    mock_zone(&server, ZONE_ID).await;
+    Mock::given(method("GET"))
+        .and(path(records_path()))
+        .and(query_param("page", "1"))
+        .respond_with(page(vec![record("1", "example.com", "192.0.2.1")], 1, 2))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(records_path()))
+        .and(query_param("page", "2"))
+        .respond_with(page(
+            vec![record("2", "www.example.com", "192.0.2.1")],
+            2,
+            2,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut records = HashMap::new();
+    update::match_subdomain_ids(
+        &client(&server, &config),
+        &config.zones[0],
+        ZONE_ID,
+        RecordType::A,
+        &mut records,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(records[&key("@")].id, "1");
+    assert_eq!(records[&key("www")].id, "2");
+}
+
+#[tokio::test]
+async fn list_zones_follows_pagination() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "");
+    for (n, name) in [(1, "example.com"), (2, "example.org")] {
+        Mock::given(method("GET"))
+            .and(path("/zones"))
+            .and(query_param("page", n.to_string()))
+            .respond_with(page(vec![json!({ "id": name, "name": name })], n, 2))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let zones = client(&server, &config).list_zones(None).await.unwrap();
+
+    let names: Vec<_> = zones.iter().map(|zone| zone.name.as_str()).collect();
+    assert_eq!(names, ["example.com", "example.org"]);
+}
+
+#[tokio::test]
+async fn match_follows_pagination_without_total_pages() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "");
+    mock_zone(&server, ZONE_ID).await;
+    for (n, result) in [
+        (1, record("1", "example.com", "192.0.2.1")),
+        (2, record("2", "www.example.com", "192.0.2.1")),
+    ] {
+        Mock::given(method("GET"))
+            .and(path(records_path()))
+            .and(query_param("page", n.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "errors": [],
+                "result": [result],
+                "result_info": { "page": n, "per_page": 1, "count": 1, "total_count": 2 },
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let mut records = HashMap::new();
+    update::match_subdomain_ids(
+        &client(&server, &config),
+        &config.zones[0],
+        ZONE_ID,
+        RecordType::A,
+        &mut records,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(records[&key("www")].id, "2");
+}
+
+#[tokio::test]
+async fn match_rejects_duplicate_records() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "");
+    mock_zone(&server, ZONE_ID).await;
+    mock_list(
+        &server,
+        vec![
+            record("1", "www.example.com", "192.0.2.1"),
+            record("2", "www.example.com", "192.0.2.2"),
+        ],
+    )
+    .await;
+
+    let mut records = HashMap::new();
+    let result = update::match_subdomain_ids(
+        &client(&server, &config),
+        &config.zones[0],
+        ZONE_ID,
+        RecordType::A,
+        &mut records,
+    )
+    .await;
+
+    match result {
+        Err(Error::Config(message)) => assert!(message.contains("ambiguous"), "{}", message),
+        other => panic!("expected a config error, got {:?}", other),
+    }
+    assert!(!records.contains_key(&key("www")));
+}
+
+#[tokio::test]
+async fn match_reports_api_errors() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "");
+    Mock::given(method("GET"))
+        .and(path(records_path()))
+        .respond_with(failure(400, 7003, "Could not route to /zones"))
+        .mount(&server)
+        .await;
+
+    let result = update::match_subdomain_ids(
+        &client(&server, &config),
+        &config.zones[0],
+        ZONE_ID,
+        RecordType::A,
+        &mut HashMap::new(),
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::Api(errors)) if errors[0].code == 7003));
+}
+
+#[tokio::test]
+async fn match_reports_rejected_credentials() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "");
+    Mock::given(method("GET"))
+        .and(path(records_path()))
+        .respond_with(failure(403, 10000, "Authentication error"))
+        .mount(&server)
+        .await;
+
+    let result = update::match_subdomain_ids(
+        &client(&server, &config),
+        &config.zones[0],
+        ZONE_ID,
+        RecordType::A,
+        &mut HashMap::new(),
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::Auth(_))));
+}
+
+/// Runs `update_dns` for the A records of the first zone.
+async fn update_dns(
+    server: &MockServer,
+    config: &Config,
+    opts: &Options,
+    records: &mut HashMap<RecordKey, Record>,
+) -> (Result<(), Error>, Summary) {
+    let mut summary = Summary::default();
+    let result = update::update_dns(
+        &client(server, config),
+        config,
+        &config.zones[0],
+        ZONE_ID,
+        RecordType::A,
+        &ip("198.51.100.4"),
+        opts,
+        records,
+        &mut summary,
+    )
+    .await;
+    (result, summary)
+}
+
+#[tokio::test]
+async fn update_dns_updates_creates_and_skips() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "  - name: new\n");
+    let mut records = HashMap::new();
+    records.insert(key("@"), known(&record("1", "example.com", "198.51.100.4")));
+    records.insert(
+        key("www"),
+        known(&record("2", "www.example.com", "192.0.2.1")),
+    );
+
+    Mock::given(method("PUT"))
+        .and(path(format!("{}/2", records_path())))
+        .and(header(
+            "authorization",
+            format!("Bearer {}", TOKEN).as_str(),
+        ))
+        .and(body_partial_json(json!({
+            "type": "A",
+            "name": "www",
+            "content": "198.51.100.4",
+            "ttl": 300,
+            "proxied": false,
+        })))
+        .respond_with(success(record("2", "www.example.com", "198.51.100.4")))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(records_path()))
+        .and(body_partial_json(
+            json!({ "name": "new", "content": "198.51.100.4" }),
+        ))
+        .respond_with(success(record("3", "new.example.com", "198.51.100.4")))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(format!("{}/1", records_path())))
+        .respond_with(success(Value::Null))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let (result, summary) = update_dns(&server, &config, &Options::default(), &mut records).await;
+
+    result.unwrap();
+    assert_eq!(
+        (summary.unchanged, summary.updated, summary.created),
+        (1, 1, 1)
+    );
+    assert!(summary.failures.is_empty());
+    assert_eq!(records[&key("www")].content, "198.51.100.4");
+    assert_eq!(records[&key("new")].id, "3");
+}
+
+#[tokio::test]
+async fn update_dns_dry_run_sends_nothing() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "");
+    let mut records = HashMap::new();
+    records.insert(
+        key("www"),
+        known(&record("2", "www.example.com", "192.0.2.1")),
+    );
+    Mock::given(wiremock::matchers::any())
+        .respond_with(success(Value::Null))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let opts = Options::new(true, false, Vec::new()).unwrap();
+    let (result, summary) = update_dns(&server, &config, &opts, &mut records).await;
+
+    result.unwrap();
+    assert_eq!((summary.updated, summary.created), (1, 1));
+    assert_eq!(records[&key("www")].content, "192.0.2.1");
+    assert!(!records.contains_key(&key("@")));
+}
+
+#[tokio::test]
+async fn update_dns_does_not_create_when_disabled() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "create_missing: false\n");
+    Mock::given(method("POST"))
+        .respond_with(success(Value::Null))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let (result, summary) =
+        update_dns(&server, &config, &Options::default(), &mut HashMap::new()).await;
+
+    result.unwrap();
+    assert_eq!(summary.failures.len(), 2);
+    assert!(summary
+        .failures
+        .iter()
+        .all(|e| matches!(e, Error::Config(m) if m.contains("create_missing"))));
+}
+
+#[tokio::test]
+async fn update_dns_continues_after_api_error() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "");
+    let mut records = HashMap::new();
+    records.insert(key("@"), known(&record("1", "example.com", "192.0.2.1")));
+    records.insert(
+        key("www"),
+        known(&record("2", "www.example.com", "192.0.2.1")),
+    );
+
+    Mock::given(method("PUT"))
+        .and(path(format!("{}/1", records_path())))
+        .respond_with(failure(400, 9005, "Content for A record is invalid"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(format!("{}/2", records_path())))
+        .respond_with(success(record("2", "www.example.com", "198.51.100.4")))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let (result, summary) = update_dns(&server, &config, &Options::default(), &mut records).await;
+
+    result.unwrap();
+    assert_eq!(summary.updated, 1);
+    assert!(matches!(&summary.failures[..], [Error::Api(errors)] if errors[0].code == 9005));
+    assert_eq!(records[&key("@")].content, "192.0.2.1");
+}
+
+#[tokio::test]
+async fn update_dns_stops_when_credentials_are_rejected() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "");
+    let mut records = HashMap::new();
+    records.insert(key("@"), known(&record("1", "example.com", "192.0.2.1")));
+    records.insert(
+        key("www"),
+        known(&record("2", "www.example.com", "192.0.2.1")),
+    );
+
+    Mock::given(method("PUT"))
+        .and(path(format!("{}/1", records_path())))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(format!("{}/2", records_path())))
+        .respond_with(success(Value::Null))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let (result, summary) = update_dns(&server, &config, &Options::default(), &mut records).await;
+
+    assert!(matches!(result, Err(Error::Auth(_))));
+    assert_eq!(summary.total(), 0);
+}
+
+/// Config for `check` with a zone looked up by name and the address reported
+/// by the mock in Cloudflare's trace format.
+fn check_config(server: &MockServer) -> Config {
+    config(
+        server,
+        &format!(
+            "zone: example.com\nttl: 300\nsubdomains:\n  - name: www\n\
+             ip_sources:\n  - type: trace\n    url: {}/cdn-cgi/trace\n",
+            server.uri()
+        ),
+    )
+}
+
+async fn mock_token(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/user/tokens/verify"))
+        .respond_with(success(json!({ "id": "t1", "status": "active" })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn check_detects_address_and_updates_records() {
+    let server = MockServer::start().await;
+    let config = check_config(&server);
+    Mock::given(method("GET"))
+        .and(path("/cdn-cgi/trace"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("fl=1\nip=198.51.100.4\nts=1\n"))
+        .mount(&server)
+        .await;
+    mock_token(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones"))
+        .and(query_param("name", "example.com"))
+        .respond_with(success(json!([{ "id": ZONE_ID, "name": "example.com" }])))
+        .expect(1)
+        .mount(&server)
+        .await;
+    mock_list(&server, vec![record("2", "www.example.com", "192.0.2.1")]).await;
+    Mock::given(method("PUT"))
+        .and(path(format!("{}/2", records_path())))
+        .and(body_partial_json(json!({ "content": "198.51.100.4" })))
+        .respond_with(success(record("2", "www.example.com", "198.51.100.4")))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let providers = Providers::new(&config).unwrap();
+    let detector = Detector::new(config.ip_sources.clone(), config.ip_consensus).unwrap();
+    let mut state = State::default();
+    update::check(
+        &providers,
+        &detector,
+        &config,
+        &Options::default(),
+        &mut state,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(state.zone_ids["example.com"], ZONE_ID);
+    assert_eq!(state.records[&key("www")].content, "198.51.100.4");
+    assert!(state.is_current(&config, &providers, RecordType::A, &ip("198.51.100.4")));
+
+    // Nothing is sent while the address stays the same.
+    update::check(
+        &providers,
+        &detector,
+        &config,
+        &Options::default(),
+        &mut state,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn check_fails_when_no_address_is_detected() {
+    let server = MockServer::start().await;
+    let config = check_config(&server);
+    Mock::given(method("GET"))
+        .and(path("/cdn-cgi/trace"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/zones"))
+        .respond_with(success(json!([])))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let detector = Detector::new(config.ip_sources.clone(), config.ip_consensus).unwrap();
+    let result = update::check(
+        &Providers::new(&config).unwrap(),
+        &detector,
+        &config,
+        &Options::default(),
+        &mut State::default(),
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::IpDetection(_))));
+}
+
+#[tokio::test]
+async fn check_fails_partially_when_one_family_is_not_detected() {
+    let server = MockServer::start().await;
+    let config = zone_config(&server, "    types: [A, AAAA]\n");
+    mock_zone(&server, ZONE_ID).await;
+    mock_token(&server).await;
+    mock_list(
+        &server,
+        vec![
+            record("1", "example.com", "198.51.100.4"),
+            record("2", "www.example.com", "198.51.100.4"),
+        ],
+    )
+    .await;
+
+    // Only IPv4 is given, so the AAAA record of www cannot be updated.
+    let opts = Options::new(false, false, vec![ip("198.51.100.4")]).unwrap();
+    let result = check(&config, &opts, &mut State::default()).await;
+
+    assert!(
+        matches!(
+            result,
+            Err(Error::Partial {
+                failed: 1,
+                total: 3
+            })
+        ),
+        "{:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn check_reports_unknown_zone() {
+    let server = MockServer::start().await;
+    let config = check_config(&server);
+    mock_token(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones"))
+        .respond_with(success(json!([])))
+        .mount(&server)
+        .await;
+
+    let opts = Options::new(false, false, vec![ip("198.51.100.4")]).unwrap();
+    let result = check(&config, &opts, &mut State::default()).await;
+
+    match result {
+        Err(Error::Config(message)) => assert!(message.contains("not found"), "{}", message),
+        other => panic!("expected a config error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn check_uses_the_provider_of_each_zone() {
+    let server = MockServer::start().await;
+    let other = MockServer::start().await;
+    let config = config(
+        &server,
+        &format!(
+            "providers:\n  other:\n    type: cloudflare\n    api_token: other-token\n    \
+             api_url: {}\nzones:\n  - zone_id: {}\n    subdomains: [{{ name: www }}]\n  \
+             - zone_id: {}\n    provider: other\n    subdomains: [{{ name: www }}]\n",
+            other.uri(),
+            ZONE_ID,
+            "other-zone"
+        ),
+    );
+    for (server, token, zone_id) in [
+        (&server, TOKEN, ZONE_ID),
+        (&other, "other-token", "other-zone"),
+    ] {
+        let bearer = format!("Bearer {}", token);
+        mock_zone(server, zone_id).await;
+        Mock::given(method("GET"))
+            .and(path("/user/tokens/verify"))
+            .and(header("authorization", bearer.as_str()))
+            .respond_with(success(json!({ "id": token, "status": "active" })))
+            .expect(1)
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/zones/{}/dns_records", zone_id)))
+            .and(header("authorization", bearer.as_str()))
+            .respond_with(page(Vec::new(), 1, 1))
+            .expect(1)
+            .mount(server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/zones/{}/dns_records", zone_id)))
+            .and(header("authorization", bearer.as_str()))
+            .respond_with(success(record("1", "www.example.com", "198.51.100.4")))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    let opts = Options::new(false, false, vec![ip("198.51.100.4")]).unwrap();
+    check(&config, &opts, &mut State::default()).await.unwrap();
+}
+
+#[tokio::test]
+async fn check_continues_when_a_provider_is_unreachable() {
+    let server = MockServer::start().await;
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let unreachable = format!("http://{}", closed.local_addr().unwrap());
+    drop(closed);
+    let config = config(
+        &server,
+        &format!(
+            "providers:\n  down:\n    type: cloudflare\n    api_token: other-token\n    \
+             api_url: {}\nzones:\n  - zone_id: other-zone\n    provider: down\n    \
+             subdomains: [{{ name: www }}]\n  - zone_id: {}\n    subdomains: [{{ name: www }}]\n",
+            unreachable, ZONE_ID
+        ),
+    );
+    mock_token(&server).await;
+    mock_zone(&server, ZONE_ID).await;
+    mock_list(&server, Vec::new()).await;
+    Mock::given(method("POST"))
+        .and(path(records_path()))
+        .respond_with(success(record("1", "www.example.com", "198.51.100.4")))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let opts = Options::new(false, false, vec![ip("198.51.100.4")]).unwrap();
+    let mut state = State::default();
+    let result = check(&config, &opts, &mut state).await;
+
+    assert!(
+        matches!(
+            result,
+            Err(Error::Partial {
+                failed: 1,
+                total: 2
+            })
+        ),
+        "{:?}",
+        result
+    );
+    assert!(state.verified.contains(&None));
+    assert!(!state.verified.contains(&Some("down".to_string())));
+}
+
+#[tokio::test]
+async fn verify_continues_when_a_provider_is_unreachable() {
+    let server = MockServer::start().await;
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let unreachable = format!("http://{}", closed.local_addr().unwrap());
+    drop(closed);
+    let config = config(
+        &server,
+        &format!(
+            "providers:\n  down:\n    type: cloudflare\n    api_token: other-token\n    \
+             api_url: {}\nzones:\n  - zone_id: other-zone\n    provider: down\n    \
+             subdomains: [{{ name: www }}]\n  - zone_id: {}\n    subdomains: [{{ name: www }}]\n",
+            unreachable, ZONE_ID
+        ),
+    );
+    mock_token(&server).await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{}", ZONE_ID)))
+        .respond_with(success(json!({
+            "id": ZONE_ID,
+            "name": "example.com",
+            "permissions": ["#dns_records:read", "#dns_records:edit"],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(records_path()))
+        .respond_with(page(Vec::new(), 1, 1))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let result = verify::run(&Providers::new(&config).unwrap(), &config).await;
+
+    assert!(matches!(result, Err(Error::Network(_))), "{:?}", result);
+}
+
+#[test]
+fn providers_without_credentials_are_a_config_error() {
+    // Not through Config::load, which turns api_token into auth.
+    let config: Config = serde_yaml::from_str(
+        "providers:\n  other:\n    type: cloudflare\n    api_token: abc\n\
+         zones:\n  - zone_id: abc\n    provider: other\n    subdomains: [{ name: www }]\n",
+    )
+    .unwrap();
+
+    let result = Providers::new(&config);
+
+    assert!(
+        matches!(&result, Err(Error::Config(m)) if m == "provider other needs api_token or auth"),
+        "{:?}",
+        result.err()
+    );
+}